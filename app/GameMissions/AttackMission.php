@@ -7,6 +7,7 @@ use OGame\GameMissions\Abstracts\GameMission;
 use OGame\GameMissions\BattleEngine\Models\BattleResult;
 use OGame\GameMissions\BattleEngine\PhpBattleEngine;
 use OGame\GameMissions\BattleEngine\RustBattleEngine;
+use OGame\GameMissions\BattleEngine\RustBattleEngineException;
 use OGame\GameMissions\Models\MissionPossibleStatus;
 use OGame\GameObjects\Models\Units\UnitCollection;
 use OGame\Models\BattleReport;
@@ -82,14 +83,23 @@ class AttackMission extends GameMission
                 break;
         }
 
-        $battleResult = $battleEngine->simulateBattle();
+        try {
+            $battleResult = $battleEngine->simulateBattle();
+        } catch (RustBattleEngineException $e) {
+            // The Rust battle engine could not process this battle. Log the error and fall back
+            // to the PHP battle engine so the mission still gets processed.
+            report($e);
+            $battleEngine = new PhpBattleEngine($attackerUnits, $attackerPlayer, $defenderPlanet, $this->settings);
+            $battleResult = $battleEngine->simulateBattle();
+        }
 
         // Deduct loot from the target planet.
         $defenderPlanet->deductResources($battleResult->loot);
 
-        // Deduct defender's lost units from the defenders planet.
+        // Deduct defender's lost units from the defenders planet. Repaired defenses are not lost.
         $defenderUnitsLost = clone $battleResult->defenderUnitsStart;
         $defenderUnitsLost->subtractCollection($battleResult->defenderUnitsResult);
+        $defenderUnitsLost->subtractCollection($battleResult->repairedDefenses);
         $defenderPlanet->removeUnits($defenderUnitsLost, false);
 
         // Save defenders planet
@@ -196,6 +206,11 @@ class AttackMission extends GameMission
             'moon_existed' => $battleResult->moonExisted,
             'moon_chance' => $battleResult->moonChance,
             'moon_created' => $battleResult->moonCreated,
+            'battle_seed' => $battleResult->battleSeed,
+            'battle_threads' => $battleResult->battleThreads,
+            'battle_unit_strategy' => $battleResult->battleUnitStrategy,
+            'battle_rule_set' => $battleResult->battleRuleSet,
+            'battle_arithmetic' => $battleResult->battleArithmetic,
         ];
 
         $report->attacker = [
@@ -229,7 +244,7 @@ class AttackMission extends GameMission
             'deuterium' => $battleResult->debris->deuterium->get(),
         ];
 
-        $report->repaired_defenses = [];
+        $report->repaired_defenses = $battleResult->repairedDefenses->toArray();
 
         $rounds = [];
         foreach ($battleResult->rounds as $round) {
@@ -41,7 +41,7 @@ abstract class BattleEngine
     /**
      * @var SettingsService The settings service.
      */
-    private SettingsService $settings;
+    protected SettingsService $settings;
 
     /**
      * BattleEngine constructor.
@@ -87,6 +87,7 @@ abstract class BattleEngine
         $result->defenderUnitsStart->addCollection($this->defenderPlanet->getShipUnits());
         $result->defenderUnitsStart->addCollection($this->defenderPlanet->getDefenseUnits());
         $result->defenderUnitsResult = clone $result->defenderUnitsStart;
+        $result->repairedDefenses = new UnitCollection();
 
         // Execute the battle rounds, this will handle the actual combat logic.
         $result->rounds = $this->fightBattleRounds($result);
@@ -82,6 +82,11 @@ class BattleResult
      */
     public Resources $defenderResourceLoss;
 
+    /**
+     * @var UnitCollection The destroyed defense units of the defender player that are repaired after the battle.
+     */
+    public UnitCollection $repairedDefenses;
+
     /**
      * @var int The attacker player's weapon technology level.
      */
@@ -116,4 +121,32 @@ class BattleResult
      * @var array<BattleResultRound> The rounds of the battle.
      */
     public array $rounds;
+
+    /**
+     * @var int|null The seed used by the battle engine for its random number generator. Only set by
+     * battle engines that support replaying a battle from its seed (RustBattleEngine).
+     */
+    public int|null $battleSeed = null;
+
+    /**
+     * @var int|null The amount of threads the battle engine used for the combat phases. Needed together
+     * with the seed to replay the battle.
+     */
+    public int|null $battleThreads = null;
+
+    /**
+     * @var string|null The unit strategy the battle engine used, e.g. "expanded" or "aggregated". Needed
+     * together with the seed to replay the battle.
+     */
+    public string|null $battleUnitStrategy = null;
+
+    /**
+     * @var string|null The rule set the battle engine used. Needed together with the seed to replay the battle.
+     */
+    public string|null $battleRuleSet = null;
+
+    /**
+     * @var string|null The arithmetic the battle engine used. Needed together with the seed to replay the battle.
+     */
+    public string|null $battleArithmetic = null;
 }
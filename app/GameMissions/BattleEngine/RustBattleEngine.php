@@ -4,7 +4,9 @@ namespace OGame\GameMissions\BattleEngine;
 
 use OGame\GameMissions\BattleEngine\Models\BattleResult;
 use OGame\GameMissions\BattleEngine\Models\BattleResultRound;
+use OGame\GameObjects\Models\Enums\GameObjectType;
 use OGame\GameObjects\Models\Units\UnitCollection;
+use OGame\Models\Resources;
 use OGame\Services\ObjectService;
 use OGame\Services\PlanetService;
 use OGame\Services\PlayerService;
@@ -21,11 +23,47 @@ use OGame\Services\SettingsService;
  */
 class RustBattleEngine extends BattleEngine
 {
+    /**
+     * Wire format flag to exchange the battle input and output with the Rust battle engine as JSON.
+     */
+    public const WIRE_FORMAT_JSON = 0;
+
+    /**
+     * Wire format flag to exchange the battle input and output with the Rust battle engine as MessagePack.
+     * Requires the msgpack PHP extension.
+     */
+    public const WIRE_FORMAT_MESSAGEPACK = 1;
+
     /**
      * @var \FFI The FFI instance used to call the Rust battle engine.
      */
     private \FFI $ffi;
 
+    /**
+     * @var int The wire format used to exchange the battle input and output with the Rust battle engine.
+     */
+    private int $wireFormat = self::WIRE_FORMAT_JSON;
+
+    /**
+     * @var int|null The seed to use for the battle. When null the Rust battle engine generates a random seed.
+     */
+    private int|null $seed = null;
+
+    /**
+     * @var Resources|null The debris field calculated by the Rust battle engine for the last fought battle.
+     */
+    private Resources|null $debris = null;
+
+    /**
+     * @var int|null The moon chance calculated by the Rust battle engine for the last fought battle.
+     */
+    private int|null $moonChance = null;
+
+    /**
+     * @var bool|null The moon creation roll of the Rust battle engine for the last fought battle.
+     */
+    private bool|null $moonCreated = null;
+
     /**
      * RustBattleEngine constructor.
      *
@@ -39,35 +77,205 @@ class RustBattleEngine extends BattleEngine
         parent::__construct($attackerFleet, $attackerPlayer, $defenderPlanet, $settings);
 
         $this->ffi = \FFI::cdef(
-            "char* fight_battle_rounds(const char* input_json);",
+            "char* fight_battle_rounds(const char* input_json);
+             void free_battle_output(char* output);
+             char* fight_battle_rounds_encoded(const char* input, size_t input_len, uint32_t format, size_t* output_len);
+             void free_battle_output_buffer(char* output, size_t output_len);",
             base_path('storage/rust-libs/libbattle_engine_ffi.so')
         );
     }
 
+    /**
+     * Set the seed for the random number generator of the battle. Using the seed of an earlier battle
+     * (see BattleResult::$battleSeed) replays that battle exactly.
+     *
+     * @param int|null $seed
+     * @return void
+     */
+    public function setSeed(int|null $seed): void
+    {
+        $this->seed = $seed;
+    }
+
+    /**
+     * Set the wire format used to exchange the battle input and output with the Rust battle engine.
+     * MessagePack is more compact and faster to decode for huge battles, but falls back to JSON when
+     * the msgpack PHP extension is not installed. Both formats result in the same battle output.
+     *
+     * @param int $wireFormat One of the WIRE_FORMAT_* constants.
+     * @return void
+     */
+    public function setWireFormat(int $wireFormat): void
+    {
+        $this->wireFormat = $wireFormat;
+    }
+
     /**
      * Fight the battle in max 6 rounds.
      *
      * @param BattleResult $result
      * @return array<BattleResultRound>
+     * @throws RustBattleEngineException When the Rust battle engine returns an error instead of a battle output.
      */
     protected function fightBattleRounds(BattleResult $result): array
     {
         // Convert PHP battle units to format expected by Rust
         $input = $this->prepareBattleInput($result);
 
+        // Call Rust function
+        if ($this->wireFormat === self::WIRE_FORMAT_MESSAGEPACK && function_exists('msgpack_pack')) {
+            $battleOutput = $this->callBattleEngineMessagePack($input);
+        } else {
+            $battleOutput = $this->callBattleEngineJson($input);
+        }
+
+        // The Rust battle engine returns an error envelope instead of the battle output when the
+        // battle could not be processed.
+        if (!is_array($battleOutput)) {
+            throw new RustBattleEngineException('invalid_output', 'Battle output could not be decoded.');
+        }
+        if (isset($battleOutput['error']) && is_array($battleOutput['error'])) {
+            throw new RustBattleEngineException(
+                (string)($battleOutput['error']['code'] ?? 'unknown'),
+                (string)($battleOutput['error']['message'] ?? ''),
+                isset($battleOutput['error']['field']) ? (string)$battleOutput['error']['field'] : null,
+                $battleOutput['error']['issues'] ?? []
+            );
+        }
+
+        // Store the seed and the settings that were used, so the battle can be replayed later.
+        $result->battleSeed = isset($battleOutput['seed']) ? (int)$battleOutput['seed'] : null;
+        $result->battleThreads = isset($battleOutput['threads']) ? (int)$battleOutput['threads'] : null;
+        $result->battleUnitStrategy = isset($battleOutput['unit_strategy']) ? (string)$battleOutput['unit_strategy'] : null;
+        $result->battleRuleSet = isset($battleOutput['rule_set']) ? (string)$battleOutput['rule_set'] : null;
+        $result->battleArithmetic = isset($battleOutput['arithmetic']) ? (string)$battleOutput['arithmetic'] : null;
+
+        // Store the destroyed defenses that are repaired after the battle.
+        if (isset($battleOutput['repaired_defenses']) && is_array($battleOutput['repaired_defenses'])) {
+            $result->repairedDefenses = $this->convertUnitArrayToUnitCollection($battleOutput['repaired_defenses']);
+        }
+
+        // Store the debris field calculated by the Rust battle engine.
+        $this->debris = null;
+        if (isset($battleOutput['debris']) && is_array($battleOutput['debris'])) {
+            $this->debris = new Resources(
+                (int)($battleOutput['debris']['metal'] ?? 0),
+                (int)($battleOutput['debris']['crystal'] ?? 0),
+                (int)($battleOutput['debris']['deuterium'] ?? 0),
+                0
+            );
+        }
+
+        // Store the moon chance and creation roll, which use the same seeded RNG as the battle so a
+        // replayed battle also reproduces the moon outcome.
+        $this->moonChance = isset($battleOutput['moon_chance']) ? (int)$battleOutput['moon_chance'] : null;
+        $this->moonCreated = isset($battleOutput['moon_created']) ? (bool)$battleOutput['moon_created'] : null;
+
+        // Convert Rust output back to PHP battle rounds
+        return $this->convertBattleOutput($battleOutput);
+    }
+
+    /**
+     * Call the Rust battle engine with the battle input encoded as JSON.
+     *
+     * @param array<string, mixed> $input
+     * @return mixed The decoded battle output.
+     */
+    private function callBattleEngineJson(array $input): mixed
+    {
         // Convert to JSON, forcing object notation
         $inputJson = json_encode($input, JSON_FORCE_OBJECT);
 
-        // Call Rust function
         // @phpstan-ignore-next-line
         $outputPtr = $this->ffi->fight_battle_rounds($inputJson);
-        $output = \FFI::string($outputPtr);
+        try {
+            $output = \FFI::string($outputPtr);
+        } finally {
+            // The output string is allocated by Rust and has to be handed back to Rust
+            // to be freed, otherwise every battle leaks its output JSON.
+            // @phpstan-ignore-next-line
+            $this->ffi->free_battle_output($outputPtr);
+        }
 
-        // Parse JSON response
-        $battleOutput = json_decode($output, true);
+        return json_decode($output, true);
+    }
 
-        // Convert Rust output back to PHP battle rounds
-        return $this->convertBattleOutput($battleOutput);
+    /**
+     * Call the Rust battle engine with the battle input encoded as MessagePack.
+     *
+     * @param array<string, mixed> $input
+     * @return mixed The decoded battle output.
+     */
+    private function callBattleEngineMessagePack(array $input): mixed
+    {
+        // Empty arrays such as units without rapidfire are encoded as lists, which the Rust battle
+        // engine accepts in place of empty maps.
+        // @phpstan-ignore-next-line
+        $inputBuffer = msgpack_pack($input);
+
+        $outputLength = $this->ffi->new('size_t');
+        // @phpstan-ignore-next-line
+        $outputPtr = $this->ffi->fight_battle_rounds_encoded($inputBuffer, strlen($inputBuffer), self::WIRE_FORMAT_MESSAGEPACK, \FFI::addr($outputLength));
+        try {
+            // @phpstan-ignore-next-line
+            $output = \FFI::string($outputPtr, $outputLength->cdata);
+        } finally {
+            // The output buffer is allocated by Rust and has to be handed back to Rust with its length.
+            // @phpstan-ignore-next-line
+            $this->ffi->free_battle_output_buffer($outputPtr, $outputLength->cdata);
+        }
+
+        // @phpstan-ignore-next-line
+        return msgpack_unpack($output);
+    }
+
+    /**
+     * Get the debris field calculated by the Rust battle engine. Falls back to the PHP calculation
+     * when the Rust battle engine did not return a debris field.
+     *
+     * @param UnitCollection $attackerUnitsLost
+     * @param UnitCollection $defenderUnitsLost
+     * @return Resources
+     */
+    protected function calculateDebris(UnitCollection $attackerUnitsLost, UnitCollection $defenderUnitsLost): Resources
+    {
+        if ($this->debris !== null) {
+            return $this->debris;
+        }
+
+        return parent::calculateDebris($attackerUnitsLost, $defenderUnitsLost);
+    }
+
+    /**
+     * Get the moon chance calculated by the Rust battle engine. Falls back to the PHP calculation
+     * when the Rust battle engine did not return a moon chance.
+     *
+     * @param Resources $debris
+     * @return int
+     */
+    protected function calculateMoonChance(Resources $debris): int
+    {
+        if ($this->moonChance !== null) {
+            return $this->moonChance;
+        }
+
+        return parent::calculateMoonChance($debris);
+    }
+
+    /**
+     * Get the moon creation roll of the Rust battle engine. Falls back to rolling in PHP when the
+     * Rust battle engine did not return a moon creation roll.
+     *
+     * @param int $moonChance
+     * @return bool
+     */
+    protected function rollMoonCreation($moonChance): bool
+    {
+        if ($this->moonCreated !== null) {
+            return $this->moonCreated;
+        }
+
+        return parent::rollMoonCreation($moonChance);
     }
 
     /**
@@ -81,7 +289,9 @@ class RustBattleEngine extends BattleEngine
      *         shield_points: int,
      *         attack_power: int,
      *         hull_plating: float,
-     *         rapidfire: array<int, int>
+     *         rapidfire: array<int, int>,
+     *         cost: array{metal: int, crystal: int, deuterium: int},
+     *         unit_type: string
      *     }>,
      *     defender_units: array<int, array{
      *         unit_id: int,
@@ -89,8 +299,31 @@ class RustBattleEngine extends BattleEngine
      *         shield_points: int,
      *         attack_power: int,
      *         hull_plating: float,
-     *         rapidfire: array<int, int>
-     *     }>
+     *         rapidfire: array<int, int>,
+     *         cost: array{metal: int, crystal: int, deuterium: int},
+     *         unit_type: string
+     *     }>,
+     *     strict: bool,
+     *     rules: array{
+     *         max_rounds: int,
+     *         shield_bounce_percentage: float,
+     *         explosion_hull_percentage: float,
+     *         shield_regeneration_percentage: float,
+     *         defense_repair_percentage: float
+     *     },
+     *     rule_set: string,
+     *     arithmetic: string,
+     *     debris: array{
+     *         from_ships_percentage: int,
+     *         from_defense_percentage: int,
+     *         deuterium_on: bool
+     *     },
+     *     moon: array{
+     *         moon_exists: bool,
+     *         max_chance: int,
+     *         debris_per_percent: int
+     *     },
+     *     seed?: int
      * }
      */
     private function prepareBattleInput(BattleResult $result): array
@@ -111,6 +344,8 @@ class RustBattleEngine extends BattleEngine
                 'attack_power' => $unit->unitObject->properties->attack->calculate($this->attackerPlayer)->totalValue,
                 'hull_plating' => floor($unit->unitObject->properties->structural_integrity->calculate($this->attackerPlayer)->totalValue / 10),
                 'rapidfire' => $rapidfire,
+                'cost' => $this->getUnitCost($unit->unitObject->price->resources),
+                'unit_type' => $unit->unitObject->type === GameObjectType::Defense ? 'defense' : 'ship',
             ];
         }
 
@@ -130,12 +365,57 @@ class RustBattleEngine extends BattleEngine
                 'attack_power' => $unit->unitObject->properties->attack->calculate($this->defenderPlanet->getPlayer())->totalValue,
                 'hull_plating' => floor($unit->unitObject->properties->structural_integrity->calculate($this->defenderPlanet->getPlayer())->totalValue / 10),
                 'rapidfire' => $rapidfire,
+                'cost' => $this->getUnitCost($unit->unitObject->price->resources),
+                'unit_type' => $unit->unitObject->type === GameObjectType::Defense ? 'defense' : 'ship',
             ];
         }
 
-        return [
+        $input = [
             'attacker_units' => $attackerUnits,
             'defender_units' => $defenderUnits,
+            // Let the Rust battle engine reject unknown input fields when running tests, so mistakes in
+            // the battle input show up in tests instead of being silently ignored in a live battle.
+            'strict' => app()->runningUnitTests(),
+            'rules' => [
+                'max_rounds' => $this->settings->battleMaxRounds(),
+                'shield_bounce_percentage' => $this->settings->battleShieldBouncePercentage(),
+                'explosion_hull_percentage' => $this->settings->battleExplosionHullPercentage(),
+                'shield_regeneration_percentage' => $this->settings->battleShieldRegenerationPercentage(),
+                'defense_repair_percentage' => $this->settings->battleDefenseRepairPercentage(),
+            ],
+            'rule_set' => $this->settings->battleRuleSet(),
+            'arithmetic' => $this->settings->battleArithmetic(),
+            'debris' => [
+                'from_ships_percentage' => $this->settings->debrisFieldFromShips(),
+                'from_defense_percentage' => $this->settings->debrisFieldFromDefense(),
+                'deuterium_on' => (bool)$this->settings->debrisFieldDeuteriumOn(),
+            ],
+            'moon' => [
+                'moon_exists' => $this->defenderPlanet->hasMoon(),
+                'max_chance' => $this->settings->maximumMoonChance(),
+                'debris_per_percent' => 100000,
+            ],
+        ];
+
+        if ($this->seed !== null) {
+            $input['seed'] = $this->seed;
+        }
+
+        return $input;
+    }
+
+    /**
+     * Get the resource cost of a single unit in the format expected by the Rust battle engine.
+     *
+     * @param Resources $price
+     * @return array{metal: int, crystal: int, deuterium: int}
+     */
+    private function getUnitCost(Resources $price): array
+    {
+        return [
+            'metal' => (int)$price->metal->get(),
+            'crystal' => (int)$price->crystal->get(),
+            'deuterium' => (int)$price->deuterium->get(),
         ];
     }
 
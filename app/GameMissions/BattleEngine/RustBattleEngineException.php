@@ -0,0 +1,57 @@
+<?php
+
+namespace OGame\GameMissions\BattleEngine;
+
+use RuntimeException;
+
+/**
+ * Class RustBattleEngineException.
+ *
+ * Thrown when the Rust battle engine returns an error envelope instead of a battle output,
+ * e.g. because the battle input was invalid or the engine panicked.
+ *
+ * @package OGame\GameMissions\BattleEngine
+ */
+class RustBattleEngineException extends RuntimeException
+{
+    /**
+     * @var string The error code returned by the Rust battle engine, e.g. "invalid_json" or "panic".
+     */
+    public string $errorCode;
+
+    /**
+     * @var string|null The path of the offending input field, e.g. "attacker_units.204.amount".
+     */
+    public string|null $field;
+
+    /**
+     * @var array<array{field: string, message: string}> All problems found during input validation.
+     */
+    public array $issues;
+
+    /**
+     * RustBattleEngineException constructor.
+     *
+     * @param string $errorCode The error code returned by the Rust battle engine.
+     * @param string $message The error message returned by the Rust battle engine.
+     * @param string|null $field The path of the offending input field.
+     * @param array<array{field: string, message: string}> $issues All problems found during input validation.
+     */
+    public function __construct(string $errorCode, string $message, string|null $field = null, array $issues = [])
+    {
+        $this->errorCode = $errorCode;
+        $this->field = $field;
+        $this->issues = $issues;
+
+        $fullMessage = 'Rust battle engine error [' . $errorCode . ']: ' . $message;
+        if (count($issues) > 0) {
+            foreach ($issues as $issue) {
+                $fullMessage .= "\n- " . $issue['field'] . ': ' . $issue['message'];
+            }
+        } elseif ($field !== null) {
+            $fullMessage .= ' (field: ' . $field . ')';
+        }
+
+        parent::__construct($fullMessage);
+    }
+}
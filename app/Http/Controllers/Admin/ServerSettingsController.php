@@ -3,6 +3,7 @@
 namespace OGame\Http\Controllers\Admin;
 
 use Illuminate\Http\RedirectResponse;
+use Illuminate\Http\Request;
 use Illuminate\View\View;
 use OGame\Http\Controllers\OGameController;
 use OGame\Services\PlayerService;
@@ -42,6 +43,13 @@ class ServerSettingsController extends OGameController
             'ignore_inactive_systems_on' => $settingsService->ignoreInactiveSystemsOn(),
             'number_of_galaxies' => $settingsService->numberOfGalaxies(),
             'battle_engine' => $settingsService->battleEngine(),
+            'battle_max_rounds' => $settingsService->battleMaxRounds(),
+            'battle_shield_bounce_percentage' => $settingsService->battleShieldBouncePercentage(),
+            'battle_explosion_hull_percentage' => $settingsService->battleExplosionHullPercentage(),
+            'battle_shield_regeneration_percentage' => $settingsService->battleShieldRegenerationPercentage(),
+            'battle_defense_repair_percentage' => $settingsService->battleDefenseRepairPercentage(),
+            'battle_rule_set' => $settingsService->battleRuleSet(),
+            'battle_arithmetic' => $settingsService->battleArithmetic(),
             'expedition_failed' => $settingsService->expeditionFailedEnabled(),
             'expedition_failed_and_delay' => $settingsService->expeditionFailedAndDelayEnabled(),
             'expedition_failed_and_speedup' => $settingsService->expeditionFailedAndSpeedupEnabled(),
@@ -57,11 +65,25 @@ class ServerSettingsController extends OGameController
     /**
      * Updates the server settings.
      *
+     * @param Request $request
      * @param SettingsService $settingsService
      * @return RedirectResponse
      */
-    public function update(SettingsService $settingsService): RedirectResponse
+    public function update(Request $request, SettingsService $settingsService): RedirectResponse
     {
+        // The battle settings are passed to the Rust battle engine, which rejects every battle with an
+        // invalid value, so they are validated before anything is saved.
+        $validated = $request->validate([
+            'battle_engine' => 'required|in:rust,php',
+            'battle_max_rounds' => 'required|integer|min:1|max:100',
+            'battle_shield_bounce_percentage' => 'required|numeric|min:0|max:100',
+            'battle_explosion_hull_percentage' => 'required|numeric|min:0|max:100',
+            'battle_shield_regeneration_percentage' => 'required|numeric|min:0|max:100',
+            'battle_defense_repair_percentage' => 'required|numeric|min:0|max:100',
+            'battle_rule_set' => 'required|in:ogamex_legacy,ogame_official',
+            'battle_arithmetic' => 'required|in:float,integer',
+        ]);
+
         $settingsService->set('fleet_speed_war', request('fleet_speed_war'));
         $settingsService->set('fleet_speed_holding', request('fleet_speed_holding'));
         $settingsService->set('fleet_speed_peaceful', request('fleet_speed_peaceful'));
@@ -87,7 +109,14 @@ class ServerSettingsController extends OGameController
         $settingsService->set('ignore_inactive_systems_on', request('ignore_inactive_systems_on', 0));
         $settingsService->set('number_of_galaxies', request('number_of_galaxies'));
 
-        $settingsService->set('battle_engine', request('battle_engine'));
+        $settingsService->set('battle_engine', $validated['battle_engine']);
+        $settingsService->set('battle_max_rounds', $validated['battle_max_rounds']);
+        $settingsService->set('battle_shield_bounce_percentage', $validated['battle_shield_bounce_percentage']);
+        $settingsService->set('battle_explosion_hull_percentage', $validated['battle_explosion_hull_percentage']);
+        $settingsService->set('battle_shield_regeneration_percentage', $validated['battle_shield_regeneration_percentage']);
+        $settingsService->set('battle_defense_repair_percentage', $validated['battle_defense_repair_percentage']);
+        $settingsService->set('battle_rule_set', $validated['battle_rule_set']);
+        $settingsService->set('battle_arithmetic', $validated['battle_arithmetic']);
 
         $settingsService->set('expedition_failed', request('expedition_failed', 0));
         $settingsService->set('expedition_failed_and_delay', request('expedition_failed_and_delay', 0));
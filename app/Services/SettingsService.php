@@ -315,7 +315,8 @@ class SettingsService
     }
 
     /**
-     * Returns the battle engine setting.
+     * Returns the battle engine setting. The combat settings below are only used by the Rust battle
+     * engine, the PHP battle engine has its own fixed values.
      *
      * @return string
      */
@@ -324,6 +325,78 @@ class SettingsService
         return $this->get('battle_engine', 'rust');
     }
 
+    /**
+     * Returns the maximum amount of rounds fought in a battle.
+     *
+     * @return int
+     */
+    public function battleMaxRounds(): int
+    {
+        return (int)$this->get('battle_max_rounds', 6);
+    }
+
+    /**
+     * Returns the percentage of the target's shield points below which a shot bounces off.
+     *
+     * @return float
+     */
+    public function battleShieldBouncePercentage(): float
+    {
+        return (float)$this->get('battle_shield_bounce_percentage', 1);
+    }
+
+    /**
+     * Returns the hull plating percentage below which a unit can explode when hit.
+     *
+     * @return float
+     */
+    public function battleExplosionHullPercentage(): float
+    {
+        return (float)$this->get('battle_explosion_hull_percentage', 70);
+    }
+
+    /**
+     * Returns the percentage of shield points regenerated at the end of every battle round.
+     *
+     * @return float
+     */
+    public function battleShieldRegenerationPercentage(): float
+    {
+        return (float)$this->get('battle_shield_regeneration_percentage', 100);
+    }
+
+    /**
+     * Returns the chance in percent for every destroyed defense unit to be repaired after a battle.
+     *
+     * @return float
+     */
+    public function battleDefenseRepairPercentage(): float
+    {
+        return (float)$this->get('battle_defense_repair_percentage', 70);
+    }
+
+    /**
+     * Returns the set of combat details used by the battle engine: 'ogamex_legacy' for the original
+     * OGameX behaviour or 'ogame_official' for the official OGame behaviour.
+     *
+     * @return string
+     */
+    public function battleRuleSet(): string
+    {
+        return $this->get('battle_rule_set', 'ogamex_legacy');
+    }
+
+    /**
+     * Returns the arithmetic used for shields, hull plating and damage by the battle engine: 'float'
+     * for 32-bit floating point numbers or 'integer' for whole numbers like the PHP battle engine.
+     *
+     * @return string
+     */
+    public function battleArithmetic(): string
+    {
+        return $this->get('battle_arithmetic', 'float');
+    }
+
     /**
      * Returns if expedition failed outcome is enabled.
      *
@@ -8,6 +8,14 @@
         </div>
     @endif
 
+    @if ($errors->any())
+        <div class="alert alert-danger">
+            @foreach ($errors->all() as $error)
+                {{ $error }}<br>
+            @endforeach
+        </div>
+    @endif
+
     <div id="resourcesettingscomponent" class="maincontent">
         <div id="planet" class="shortHeader">
             <h2>@lang('Server settings')</h2>
@@ -202,6 +210,61 @@
                                     </select>
                                 </div>
                             </div>
+                            <div class="fieldwrapper">
+                                <label class="styled textBeefy">@lang('Maximum battle rounds:')</label>
+                                <div class="thefield">
+                                    <input type="text" pattern="[0-9]*" class="textInput w50 textCenter textBeefy" value="{{ $battle_max_rounds }}" size="6" name="battle_max_rounds">
+                                </div>
+                                <div class="smallFont">@lang('Only applies to the Rust battle engine. OGame default is 6.')</div>
+                            </div>
+                            <div class="fieldwrapper">
+                                <label class="styled textBeefy">@lang('Shield bounce threshold (%):')</label>
+                                <div class="thefield">
+                                    <input type="text" pattern="[0-9]*\.?[0-9]*" class="textInput w50 textCenter textBeefy" value="{{ $battle_shield_bounce_percentage }}" size="6" name="battle_shield_bounce_percentage">
+                                </div>
+                                <div class="smallFont">@lang('Shots weaker than this percentage of the target shield bounce off. Only applies to the Rust battle engine. OGame default is 1%.')</div>
+                            </div>
+                            <div class="fieldwrapper">
+                                <label class="styled textBeefy">@lang('Explosion hull threshold (%):')</label>
+                                <div class="thefield">
+                                    <input type="text" pattern="[0-9]*\.?[0-9]*" class="textInput w50 textCenter textBeefy" value="{{ $battle_explosion_hull_percentage }}" size="6" name="battle_explosion_hull_percentage">
+                                </div>
+                                <div class="smallFont">@lang('Units with hull below this percentage can explode when hit. Only applies to the Rust battle engine. OGame default is 70%.')</div>
+                            </div>
+                            <div class="fieldwrapper">
+                                <label class="styled textBeefy">@lang('Shield regeneration per round (%):')</label>
+                                <div class="thefield">
+                                    <input type="text" pattern="[0-9]*\.?[0-9]*" class="textInput w50 textCenter textBeefy" value="{{ $battle_shield_regeneration_percentage }}" size="6" name="battle_shield_regeneration_percentage">
+                                </div>
+                                <div class="smallFont">@lang('Only applies to the Rust battle engine. OGame default is 100%.')</div>
+                            </div>
+                            <div class="fieldwrapper">
+                                <label class="styled textBeefy">@lang('Defense repair chance (%):')</label>
+                                <div class="thefield">
+                                    <input type="text" pattern="[0-9]*\.?[0-9]*" class="textInput w50 textCenter textBeefy" value="{{ $battle_defense_repair_percentage }}" size="6" name="battle_defense_repair_percentage">
+                                </div>
+                                <div class="smallFont">@lang('Chance for every destroyed defense unit to be repaired after battle. Only applies to the Rust battle engine. OGame default is 70%.')</div>
+                            </div>
+                            <div class="fieldwrapper">
+                                <label class="styled textBeefy">@lang('Combat rule set:')</label>
+                                <div class="thefield">
+                                    <select name="battle_rule_set" class="w130" data-value="{{ $battle_rule_set }}">
+                                        <option value="ogamex_legacy"{{ $battle_rule_set == 'ogamex_legacy' ? ' selected' : '' }}>OGameX legacy</option>
+                                        <option value="ogame_official"{{ $battle_rule_set == 'ogame_official' ? ' selected' : '' }}>OGame official</option>
+                                    </select>
+                                </div>
+                                <div class="smallFont">@lang('OGame official uses the exact rapidfire chance, bounces shots off the current shields and only lets units explode from shots that damage the hull. Only applies to the Rust battle engine. A battle seed only replays a battle with the rule set it was fought with.')</div>
+                            </div>
+                            <div class="fieldwrapper">
+                                <label class="styled textBeefy">@lang('Combat arithmetic:')</label>
+                                <div class="thefield">
+                                    <select name="battle_arithmetic" class="w130" data-value="{{ $battle_arithmetic }}">
+                                        <option value="float"{{ $battle_arithmetic == 'float' ? ' selected' : '' }}>Float</option>
+                                        <option value="integer"{{ $battle_arithmetic == 'integer' ? ' selected' : '' }}>Integer</option>
+                                    </select>
+                                </div>
+                                <div class="smallFont">@lang('Integer truncates shields, hull plating and damage to whole numbers like the PHP battle engine, so both engines report the same damage. Only applies to the Rust battle engine. A battle seed only replays a battle with the arithmetic it was fought with.')</div>
+                            </div>
                         </div>
 
                         <p class="box_highlight textCenter no_buddies">@lang('Expedition settings.')</p>
@@ -1,6 +1,4 @@
-use battle_engine_ffi;
 use serde_json::Result;
-use libc;
 
 fn main() -> Result<()> {
 
@@ -49,7 +47,7 @@ fn main() -> Result<()> {
     This has caused eternal loop before:
     {"attacker_units":{"204": {"unit_id":204,"amount":5000,"shield_points":10,"attack_power":50,"hull_plating":400,"rapidfire":{"210":5,"212":5}}},"defender_units":{"408": {"unit_id":408,"amount":1,"shield_points":10000,"attack_power":1,"hull_plating":10000,"rapidfire":{}}}}
 
-    10M units battle for memory usage debug:
+    10M units battle for memory usage debug, add "unit_strategy":"aggregated" to compare the memory usage with grouped units:
     {"attacker_units":{"204": {"unit_id":204,"amount":5000000,"shield_points":10,"attack_power":50,"hull_plating":400,"rapidfire":{"210":5,"212":5}}},"defender_units":{"401": {"unit_id":401,"amount":5000000,"shield_points":20,"attack_power":80,"hull_plating":200,"rapidfire":{}}}}
 
      */
@@ -62,12 +60,12 @@ fn main() -> Result<()> {
     let c_input = std::ffi::CString::new(json_input).unwrap();
 
     // Call the FFI interface directly
-    let output_ptr = battle_engine_ffi::fight_battle_rounds(c_input.as_ptr());
+    let output_ptr = unsafe { battle_engine_ffi::fight_battle_rounds(c_input.as_ptr()) };
 
-    // Convert output back to string and free memory
+    // Convert output back to string and hand the memory back to the library that allocated it
     let output = unsafe {
         let output_str = std::ffi::CStr::from_ptr(output_ptr).to_string_lossy().into_owned();
-        libc::free(output_ptr as *mut libc::c_void);
+        battle_engine_ffi::free_battle_output(output_ptr);
         output_str
     };
 
@@ -0,0 +1,61 @@
+//! Benchmark of the combat loop, which reports the time per shot of a fixed battle.
+//!
+//! Run with `cargo run --release --example combat_benchmark -- [input.json] [calls]`. Without an input
+//! file the fleet of the PHP performance test in the README is used, with `"seed": 42` so every call
+//! fights the same battle.
+
+use std::ffi::{CStr, CString};
+use std::time::Instant;
+
+use battle_engine_ffi::{fight_battle_rounds, free_battle_output};
+
+const DEFAULT_INPUT: &str = r#"{
+    "seed": 42,
+    "attacker_units": {
+        "206": {"unit_id": 206, "amount": 700000, "attack_power": 400, "shield_points": 50, "hull_plating": 2700, "rapidfire": {"210": 5, "212": 5, "204": 6, "401": 10}},
+        "207": {"unit_id": 207, "amount": 100000, "attack_power": 1000, "shield_points": 200, "hull_plating": 6000, "rapidfire": {"210": 5, "212": 5}}
+    },
+    "defender_units": {
+        "406": {"unit_id": 406, "amount": 20000, "attack_power": 3000, "shield_points": 300, "hull_plating": 10000, "rapidfire": {}},
+        "401": {"unit_id": 401, "amount": 100000, "attack_power": 80, "shield_points": 20, "hull_plating": 200, "rapidfire": {}}
+    }
+}"#;
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let input = match args.next() {
+        Some(path) => std::fs::read_to_string(path).expect("the input file can be read"),
+        None => DEFAULT_INPUT.to_owned(),
+    };
+    let calls: usize = args.next().map_or(25, |calls| calls.parse().expect("the amount of calls is a number"));
+    let input = CString::new(input).unwrap();
+
+    let mut shots = 0;
+    let mut times = Vec::with_capacity(calls);
+    for _ in 0..calls {
+        let start = Instant::now();
+        let output = unsafe { fight_battle_rounds(input.as_ptr()) };
+        times.push(start.elapsed().as_secs_f64());
+
+        let output_json = unsafe { CStr::from_ptr(output) }.to_str().unwrap().to_owned();
+        unsafe { free_battle_output(output) };
+        shots = count_shots(&output_json);
+    }
+    times.sort_by(|a, b| a.total_cmp(b));
+
+    let (fastest, median) = (times[0], times[times.len() / 2]);
+    let per_shot = |seconds: f64| seconds * 1e9 / shots as f64;
+    println!("calls: {}, shots per call: {}", calls, shots);
+    println!("fastest call: {:.1}ms ({:.2}ns per shot)", fastest * 1e3, per_shot(fastest));
+    println!("median call: {:.1}ms ({:.2}ns per shot)", median * 1e3, per_shot(median));
+}
+
+/// Count the hits of both sides in all rounds of the battle output.
+fn count_shots(output_json: &str) -> u64 {
+    let output: serde_json::Value = serde_json::from_str(output_json).unwrap();
+    let rounds = output["rounds"].as_array().expect("the battle output contains rounds");
+    rounds
+        .iter()
+        .map(|round| round["hits_attacker"].as_u64().unwrap() + round["hits_defender"].as_u64().unwrap())
+        .sum()
+}
@@ -0,0 +1,154 @@
+use crate::BattleRng;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+/// Arithmetic used for the shield points, hull plating and damage of the units during the battle.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Arithmetic {
+    /// 32-bit floating point numbers, as used before the arithmetic could be chosen. Values above
+    /// 16,777,216 lose precision.
+    #[default]
+    Float,
+    /// Whole numbers, truncated the same as the integer properties of the PHP engine, so the damage and
+    /// shield absorption of the battle rounds match the PHP engine exactly. The unit stats must be at
+    /// most `MAX_INTEGER_STAT`.
+    Integer,
+}
+
+/// Highest unit stat which is allowed with integer arithmetic. Unit stats are parsed as 32-bit
+/// floating point numbers, which represent every whole number up to this value exactly.
+pub const MAX_INTEGER_STAT: f32 = 16_777_216.0;
+
+/// Shield points, hull plating or damage of a unit, stored as `f32` for float arithmetic and as `u32`
+/// for integer arithmetic.
+pub trait Points: Copy + PartialOrd + Send + Sync {
+    const ZERO: Self;
+
+    /// Convert a unit stat from the battle input.
+    fn from_stat(stat: f32) -> Self;
+
+    /// Convert the points for the round statistics.
+    fn to_f64(self) -> f64;
+
+    /// Points that are left after the damage is subtracted.
+    fn take_damage(self, damage: Self) -> Self;
+
+    /// Whether these points are below the given percentage of `total`.
+    fn is_below_percentage(self, total: Self, percentage: f32) -> bool;
+
+    /// Roll whether a unit with the given hull plating explodes. Units with a hull integrity below
+    /// `explosion_percentage` have a chance of 100% minus the hull integrity to explode, rolled with a
+    /// dice from 0 to 100.
+    fn explodes(current_hull_plating: Self, hull_plating: Self, explosion_percentage: f32, rng: &mut BattleRng) -> bool;
+
+    /// Regenerate the given percentage of `total` at the end of a round, capped at `total`.
+    fn regenerate(self, total: Self, percentage: f32) -> Self;
+}
+
+impl Points for f32 {
+    const ZERO: Self = 0.0;
+
+    fn from_stat(stat: f32) -> Self {
+        stat
+    }
+
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+
+    fn take_damage(self, damage: Self) -> Self {
+        self - damage
+    }
+
+    fn is_below_percentage(self, total: Self, percentage: f32) -> bool {
+        self < percentage / 100.0 * total
+    }
+
+    fn explodes(current_hull_plating: Self, hull_plating: Self, explosion_percentage: f32, rng: &mut BattleRng) -> bool {
+        let hull_integrity = current_hull_plating / hull_plating;
+        if hull_integrity >= explosion_percentage / 100.0 {
+            return false;
+        }
+
+        let explosion_chance = 100.0 - (hull_integrity * 100.0);
+        let roll = rng.gen_range(0..=100);
+        roll < explosion_chance as i32
+    }
+
+    fn regenerate(self, total: Self, percentage: f32) -> Self {
+        (self + total * (percentage / 100.0)).min(total)
+    }
+}
+
+/// Integer points follow the PHP engine: stats are truncated to whole numbers, hull plating can't drop
+/// below 0 and percentages are calculated with 64-bit floating point numbers like PHP does.
+impl Points for u32 {
+    const ZERO: Self = 0;
+
+    fn from_stat(stat: f32) -> Self {
+        stat as u32
+    }
+
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+
+    fn take_damage(self, damage: Self) -> Self {
+        self.saturating_sub(damage)
+    }
+
+    fn is_below_percentage(self, total: Self, percentage: f32) -> bool {
+        (self as f64) < percentage as f64 / 100.0 * total as f64
+    }
+
+    fn explodes(current_hull_plating: Self, hull_plating: Self, explosion_percentage: f32, rng: &mut BattleRng) -> bool {
+        let hull_integrity = current_hull_plating as f64 / hull_plating as f64;
+        if hull_integrity >= explosion_percentage as f64 / 100.0 {
+            return false;
+        }
+
+        // The chance is truncated like with floating point stats, so both arithmetic modes explode the
+        // same units for the same hull integrity.
+        let explosion_chance = (1.0 - hull_integrity) * 100.0;
+        let roll = rng.gen_range(0..=100);
+        roll < explosion_chance as i32
+    }
+
+    fn regenerate(self, total: Self, percentage: f32) -> Self {
+        let regenerated = (total as f64 * percentage as f64 / 100.0) as u32;
+        self.saturating_add(regenerated).min(total)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    #[test]
+    fn integer_stats_are_truncated() {
+        assert_eq!(u32::from_stat(55.9), 55);
+        assert_eq!(u32::from_stat(MAX_INTEGER_STAT), 16_777_216);
+    }
+
+    #[test]
+    fn integer_shield_regeneration_is_truncated() {
+        assert_eq!(7u32.regenerate(15, 50.0), 14);
+        assert_eq!(7.0f32.regenerate(15.0, 50.0), 14.5);
+        assert_eq!(7u32.regenerate(15, 100.0), 15);
+    }
+
+    #[test]
+    fn both_arithmetic_modes_truncate_the_explosion_chance() {
+        // A hull integrity of 30.5% gives an explosion chance of 69.5%, which is truncated to 69%.
+        let rolls = |explodes: &dyn Fn(&mut BattleRng) -> bool| {
+            let mut rng = BattleRng::seed_from_u64(42);
+            (0..1000).map(|_| explodes(&mut rng)).collect::<Vec<_>>()
+        };
+
+        let float_rolls = rolls(&|rng| f32::explodes(61.0, 200.0, 70.0, rng));
+        let integer_rolls = rolls(&|rng| u32::explodes(61, 200, 70.0, rng));
+        assert_eq!(float_rolls, integer_rolls);
+    }
+}
@@ -0,0 +1,131 @@
+use crate::error::{BattleError, BattleErrorCode, ValidationIssue};
+use crate::format::WireFormat;
+use crate::parallel;
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+/// Batch of battles which is provided by the PHP client, e.g. when the mission processor catches up
+/// on many fleet arrivals at once.
+#[derive(Deserialize)]
+struct BattleBatchInput {
+    battles: Vec<BatchBattle>,
+    /// Amount of battles that are processed at the same time. Defaults to 1.
+    #[serde(default)]
+    threads: Option<usize>,
+}
+
+/// Single battle of a batch.
+#[derive(Deserialize)]
+struct BatchBattle {
+    /// Id of the battle as known by the PHP client, e.g. the fleet mission id.
+    id: u64,
+    /// Battle input, the same as for `fight_battle_rounds`. It is parsed separately for every battle,
+    /// so an invalid battle input only fails that battle.
+    input: Box<RawValue>,
+}
+
+/// Result of a single battle of a batch: either the battle output or the error.
+#[derive(Serialize)]
+struct BatchResult {
+    id: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    output: Option<Box<RawValue>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<BattleError>,
+}
+
+/// Battle batch output which is returned to the PHP client.
+#[derive(Serialize)]
+struct BattleBatchOutput {
+    /// Results of all battles, in the same order as the battles in the input.
+    results: Vec<BatchResult>,
+}
+
+/// Process a batch of battles and return the results as JSON.
+///
+/// Every battle is processed the same as by `fight_battle_rounds` with its own seed, so the results
+/// don't depend on the amount of threads of the batch. Allocation metrics of battles are only
+/// accurate when the battles of the batch are processed one at a time.
+pub fn fight_battles_batch(input_str: &str) -> Result<String, BattleError> {
+    let deserializer = &mut serde_json::Deserializer::from_str(input_str);
+    let batch: BattleBatchInput = serde_path_to_error::deserialize(deserializer)?;
+
+    let threads = batch.threads.unwrap_or(1);
+    if !(1..=parallel::MAX_THREADS).contains(&threads) {
+        return Err(BattleError::from_issues(vec![ValidationIssue::new(
+            "threads",
+            format!("must be between 1 and {}", parallel::MAX_THREADS),
+        )]));
+    }
+
+    let results = if threads == 1 {
+        batch.battles.iter().map(fight_batch_battle).collect()
+    } else {
+        fight_batch_battles_parallel(&batch.battles, threads)
+    };
+
+    serde_json::to_string(&BattleBatchOutput { results })
+        .map_err(|error| BattleError::new(BattleErrorCode::Serialization, error.to_string()))
+}
+
+/// Process the battles with multiple threads. Every thread takes the next unprocessed battle until
+/// all battles are processed, so a single large battle doesn't hold up the others.
+fn fight_batch_battles_parallel(battles: &[BatchBattle], threads: usize) -> Vec<BatchResult> {
+    let next_battle = AtomicUsize::new(0);
+    let mut results: Vec<Option<BatchResult>> = battles.iter().map(|_| None).collect();
+
+    thread::scope(|scope| {
+        let handles: Vec<_> = (0..threads.min(battles.len()))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut results = Vec::new();
+                    loop {
+                        let index = next_battle.fetch_add(1, Ordering::Relaxed);
+                        let Some(battle) = battles.get(index) else {
+                            break;
+                        };
+                        results.push((index, fight_batch_battle(battle)));
+                    }
+
+                    results
+                })
+            })
+            .collect();
+
+        for handle in handles {
+            for (index, result) in handle.join().expect("batch thread panicked") {
+                results[index] = Some(result);
+            }
+        }
+    });
+
+    results.into_iter().map(|result| result.expect("every battle is processed")).collect()
+}
+
+/// Process a single battle of the batch. Errors and panics only fail this battle.
+fn fight_batch_battle(battle: &BatchBattle) -> BatchResult {
+    let output = panic::catch_unwind(AssertUnwindSafe(|| crate::fight_battle(battle.input.get().as_bytes(), WireFormat::Json)))
+        .unwrap_or_else(|payload| Err(BattleError::from_panic(payload)))
+        .and_then(|output_json| {
+            String::from_utf8(output_json)
+                .map_err(|error| error.to_string())
+                .and_then(|output_json| RawValue::from_string(output_json).map_err(|error| error.to_string()))
+                .map_err(|message| BattleError::new(BattleErrorCode::Serialization, message))
+        });
+
+    match output {
+        Ok(output) => BatchResult {
+            id: battle.id,
+            output: Some(output),
+            error: None,
+        },
+        Err(error) => BatchResult {
+            id: battle.id,
+            output: None,
+            error: Some(error),
+        },
+    }
+}
@@ -0,0 +1,189 @@
+use crate::rules::MAX_RAPIDFIRE;
+use crate::stats::Technologies;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Player class of the owner of a participant.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum PlayerClass {
+    Collector,
+    General,
+    Discoverer,
+}
+
+/// Officer which is hired by the owner of a participant.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Officer {
+    Commander,
+    Admiral,
+    Engineer,
+    Geologist,
+    Technocrat,
+    /// All officers together, which can have a bonus of its own.
+    CommandingStaff,
+}
+
+/// Combat bonus of a player class or officer.
+///
+/// The research levels and bonus percentages are added to the technologies of the participant, which
+/// changes the stats of all its units. The extra rapidfire applies to all units.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct CombatBonus {
+    pub weapon_technology: u32,
+    pub shielding_technology: u32,
+    pub armor_technology: u32,
+    pub attack_bonus_percentage: f32,
+    pub shield_bonus_percentage: f32,
+    pub structural_integrity_bonus_percentage: f32,
+    /// Rapidfire which is added to the rapidfire of the units.
+    pub rapidfire: Vec<RapidfireBonus>,
+}
+
+/// Rapidfire which is added to the rapidfire of units with `unit_id` against units with
+/// `target_unit_id`. Units without rapidfire against the target get this rapidfire.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RapidfireBonus {
+    pub unit_id: i16,
+    pub target_unit_id: i16,
+    pub amount: u16,
+}
+
+/// Combat bonuses of the player classes and officers, which can be configured per universe.
+///
+/// Classes and officers that are missing from the table have no combat bonus. When omitted from the
+/// battle input, the General gets 2 extra levels of every combat research. The extra rapidfire of the
+/// classes and the bonuses of officers differ too much between universes to have a default.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct BonusTable {
+    pub classes: HashMap<PlayerClass, CombatBonus>,
+    pub officers: HashMap<Officer, CombatBonus>,
+}
+
+impl Default for BonusTable {
+    fn default() -> Self {
+        let general = CombatBonus {
+            weapon_technology: 2,
+            shielding_technology: 2,
+            armor_technology: 2,
+            ..CombatBonus::default()
+        };
+
+        BonusTable {
+            classes: HashMap::from([(PlayerClass::General, general)]),
+            officers: HashMap::new(),
+        }
+    }
+}
+
+impl BonusTable {
+    /// Get the bonuses of the class and officers of a participant, in that order.
+    pub fn bonuses<'a>(&'a self, technologies: &'a Technologies) -> impl Iterator<Item = &'a CombatBonus> {
+        let class_bonus = technologies.player_class.and_then(|player_class| self.classes.get(&player_class));
+        let officer_bonuses = technologies.officers.iter().filter_map(|officer| self.officers.get(officer));
+
+        class_bonus.into_iter().chain(officer_bonuses)
+    }
+
+    /// Get the technologies of a participant including the bonuses of its class and officers.
+    pub fn apply(&self, technologies: &Technologies) -> Technologies {
+        let mut result = technologies.clone();
+        for bonus in self.bonuses(technologies) {
+            result.weapon_technology = result.weapon_technology.saturating_add(bonus.weapon_technology);
+            result.shielding_technology = result.shielding_technology.saturating_add(bonus.shielding_technology);
+            result.armor_technology = result.armor_technology.saturating_add(bonus.armor_technology);
+            result.attack_bonus_percentage += bonus.attack_bonus_percentage;
+            result.shield_bonus_percentage += bonus.shield_bonus_percentage;
+            result.structural_integrity_bonus_percentage += bonus.structural_integrity_bonus_percentage;
+        }
+
+        result
+    }
+
+    /// Add the extra rapidfire of the class and officers of a participant to the rapidfire of a unit,
+    /// capped at `MAX_RAPIDFIRE`.
+    pub fn apply_rapidfire(&self, technologies: &Technologies, unit_id: i16, rapidfire: &mut HashMap<i16, u16>) {
+        for bonus in self.bonuses(technologies) {
+            for rapidfire_bonus in bonus.rapidfire.iter().filter(|rapidfire_bonus| rapidfire_bonus.unit_id == unit_id) {
+                let amount = rapidfire.entry(rapidfire_bonus.target_unit_id).or_insert(0);
+                *amount = amount.saturating_add(rapidfire_bonus.amount).min(MAX_RAPIDFIRE);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bonuses_of_class_and_officers_are_added() {
+        let mut table = BonusTable::default();
+        table.officers.insert(
+            Officer::Admiral,
+            CombatBonus {
+                attack_bonus_percentage: 5.0,
+                rapidfire: vec![RapidfireBonus {
+                    unit_id: 207,
+                    target_unit_id: 204,
+                    amount: 3,
+                }],
+                ..CombatBonus::default()
+            },
+        );
+        let technologies = Technologies {
+            weapon_technology: 10,
+            player_class: Some(PlayerClass::General),
+            officers: vec![Officer::Admiral, Officer::Geologist],
+            ..Technologies::default()
+        };
+
+        let result = table.apply(&technologies);
+        assert_eq!(result.weapon_technology, 12);
+        assert_eq!(result.armor_technology, 2);
+        assert_eq!(result.attack_bonus_percentage, 5.0);
+
+        let mut rapidfire = HashMap::from([(204, 2), (205, 4)]);
+        table.apply_rapidfire(&technologies, 207, &mut rapidfire);
+        assert_eq!(rapidfire, HashMap::from([(204, 5), (205, 4)]));
+        table.apply_rapidfire(&technologies, 206, &mut rapidfire);
+        assert_eq!(rapidfire, HashMap::from([(204, 5), (205, 4)]));
+    }
+
+    #[test]
+    fn rapidfire_with_bonuses_is_capped() {
+        let mut table = BonusTable::default();
+        table.officers.insert(
+            Officer::Admiral,
+            CombatBonus {
+                rapidfire: vec![RapidfireBonus {
+                    unit_id: 214,
+                    target_unit_id: 210,
+                    amount: MAX_RAPIDFIRE,
+                }],
+                ..CombatBonus::default()
+            },
+        );
+        let technologies = Technologies {
+            officers: vec![Officer::Admiral],
+            ..Technologies::default()
+        };
+
+        let mut rapidfire = HashMap::from([(210, 1250)]);
+        table.apply_rapidfire(&technologies, 214, &mut rapidfire);
+        assert_eq!(rapidfire[&210], MAX_RAPIDFIRE);
+    }
+
+    #[test]
+    fn participants_without_class_or_officers_have_no_bonus() {
+        let technologies = Technologies {
+            weapon_technology: 10,
+            ..Technologies::default()
+        };
+
+        assert_eq!(BonusTable::default().apply(&technologies), technologies);
+    }
+}
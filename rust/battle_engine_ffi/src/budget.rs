@@ -0,0 +1,138 @@
+use crate::error::{BattleError, BattleErrorCode};
+use crate::parallel;
+use crate::rules::{RuleSet, MAX_RAPIDFIRE};
+use crate::units::{UnitGroup, UnitStrategy, UnitTypeIndex};
+use crate::BattleParticipant;
+use std::mem::size_of;
+
+/// Environment variable which sets the memory budget in kilobytes when the battle input does not.
+pub const MEMORY_BUDGET_ENV_VAR: &str = "BATTLE_ENGINE_MEMORY_BUDGET";
+
+/// Memory used by a single individually stored unit: its group index, shield points and hull plating.
+/// Float and integer points have the same size.
+const UNIT_BYTES: f64 = (size_of::<u32>() + 2 * size_of::<f32>()) as f64;
+
+/// Get the memory budget in kilobytes for the battle: the budget from the battle input, or else from
+/// the `BATTLE_ENGINE_MEMORY_BUDGET` environment variable. Without a budget the memory usage of a
+/// battle is not limited.
+///
+/// Invalid values in the environment variable are ignored, as the PHP client can't be told about them.
+pub fn resolve_memory_budget(input_memory_budget: Option<u64>) -> Option<u64> {
+    input_memory_budget
+        .or_else(|| std::env::var(MEMORY_BUDGET_ENV_VAR).ok()?.trim().parse().ok())
+        .filter(|memory_budget| *memory_budget > 0)
+}
+
+/// Select the unit strategy for the battle based on the estimated memory usage, before any units are
+/// created.
+///
+/// When the requested strategy doesn't fit in the memory budget, the engine switches to the
+/// aggregated strategy if that does fit. Otherwise a `battle_too_large` error is returned instead of
+/// risking the process to be killed for running out of memory.
+///
+/// Returns the selected strategy and its estimated memory usage in kilobytes.
+pub fn select_unit_strategy(
+    attackers: &[BattleParticipant],
+    defenders: &[BattleParticipant],
+    unit_types: &UnitTypeIndex,
+    requested: UnitStrategy,
+    max_rounds: u32,
+    threads: usize,
+    memory_budget: Option<u64>,
+) -> Result<(UnitStrategy, u64), BattleError> {
+    let estimate = |strategy| estimate_memory(attackers, defenders, unit_types, strategy, max_rounds, threads);
+
+    let requested_memory = estimate(requested);
+    let Some(memory_budget) = memory_budget else {
+        return Ok((requested, requested_memory));
+    };
+    if requested_memory <= memory_budget {
+        return Ok((requested, requested_memory));
+    }
+
+    let aggregated_memory = estimate(UnitStrategy::Aggregated);
+    if aggregated_memory <= memory_budget {
+        return Ok((UnitStrategy::Aggregated, aggregated_memory));
+    }
+
+    Err(BattleError::new(
+        BattleErrorCode::BattleTooLarge,
+        format!(
+            "battle needs an estimated {} KB of memory, which exceeds the memory budget of {} KB",
+            requested_memory.min(aggregated_memory),
+            memory_budget
+        ),
+    ))
+}
+
+/// Estimate the peak memory usage in kilobytes of the units of both sides with the given strategy.
+///
+/// The estimate is an upper bound: with the aggregated strategy every hit is assumed to damage a
+/// different unit, and every unit is assumed to fire the longest possible rapidfire chain on average.
+fn estimate_memory(
+    attackers: &[BattleParticipant],
+    defenders: &[BattleParticipant],
+    unit_types: &UnitTypeIndex,
+    strategy: UnitStrategy,
+    max_rounds: u32,
+    threads: usize,
+) -> u64 {
+    let attacker_units = count_units(attackers);
+    let defender_units = count_units(defenders);
+
+    // Maximum amount of hits a side takes in a single combat phase.
+    let hits_on_attackers = defender_units * max_shots_per_unit(defenders, attackers);
+    let hits_on_defenders = attacker_units * max_shots_per_unit(attackers, defenders);
+
+    let mut bytes = group_memory(attackers, unit_types) + group_memory(defenders, unit_types);
+    bytes += match strategy {
+        UnitStrategy::Expanded => (attacker_units + defender_units) * UNIT_BYTES,
+        // Only damaged units are stored individually, which are added one by one so the vectors can
+        // have up to twice the capacity that is used.
+        UnitStrategy::Aggregated => {
+            let damaged_attackers = attacker_units.min(hits_on_attackers * max_rounds as f64);
+            let damaged_defenders = defender_units.min(hits_on_defenders * max_rounds as f64);
+            (damaged_attackers + damaged_defenders) * UNIT_BYTES * 2.0
+        }
+    };
+
+    // Multi-threaded combat phases buffer the shots of a phase before applying them.
+    if threads > 1 {
+        bytes += parallel::shot_memory(hits_on_attackers.max(hits_on_defenders));
+    }
+
+    (bytes / 1024.0).ceil() as u64
+}
+
+/// Total amount of units of one side.
+fn count_units(participants: &[BattleParticipant]) -> f64 {
+    participants
+        .iter()
+        .flat_map(|participant| participant.units.values())
+        .map(|unit| unit.amount as f64)
+        .sum()
+}
+
+/// Maximum average amount of shots a single unit of the shooting side can fire in a combat phase,
+/// based on the highest rapidfire against any unit of the other side.
+///
+/// The rapidfire chance of the legacy rule set is never lower than the official one, so it is used as
+/// upper bound for both rule sets. Rapidfire above the maximum rapidfire is capped, so the amount of
+/// shots stays finite.
+fn max_shots_per_unit(shooters: &[BattleParticipant], targets: &[BattleParticipant]) -> f64 {
+    let is_target = |unit_id: &i16| targets.iter().any(|participant| participant.units.contains_key(unit_id));
+
+    shooters
+        .iter()
+        .flat_map(|participant| participant.units.values())
+        .flat_map(|unit| &unit.rapidfire)
+        .filter(|(target_id, _)| is_target(target_id))
+        .map(|(_, rapidfire_amount)| 1.0 / (1.0 - RuleSet::OgamexLegacy.rapidfire_chance((*rapidfire_amount).min(MAX_RAPIDFIRE)) / 100.0))
+        .fold(1.0, f64::max)
+}
+
+/// Memory used by the unit groups of one side and their rapidfire table.
+fn group_memory(participants: &[BattleParticipant], unit_types: &UnitTypeIndex) -> f64 {
+    let groups: usize = participants.iter().map(|participant| participant.units.len()).sum();
+    (groups * (size_of::<UnitGroup<f32>>() + unit_types.len() * size_of::<Option<f64>>())) as f64
+}
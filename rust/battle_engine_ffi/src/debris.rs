@@ -0,0 +1,179 @@
+use crate::{BattleParticipant, BattleRules, ParticipantRound, Resources, UnitType};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Debris field settings which are provided by the PHP client.
+///
+/// These mirror the `debris_field_*` server settings. Missing settings fall back to the
+/// OGameX defaults.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct DebrisSettings {
+    /// Percentage of the resource cost of destroyed ships that ends up in the debris field.
+    pub from_ships_percentage: f64,
+    /// Percentage of the resource cost of destroyed defenses that ends up in the debris field.
+    pub from_defense_percentage: f64,
+    /// Whether deuterium ends up in the debris field as well.
+    pub deuterium_on: bool,
+}
+
+impl Default for DebrisSettings {
+    fn default() -> Self {
+        DebrisSettings {
+            from_ships_percentage: 30.0,
+            from_defense_percentage: 0.0,
+            deuterium_on: false,
+        }
+    }
+}
+
+/// Units of the same type and cost, which are rounded down together when calculating debris.
+#[derive(Hash, PartialEq, Eq)]
+struct DebrisGroup {
+    unit_id: i16,
+    cost: Resources,
+    unit_type: UnitType,
+}
+
+/// Calculate the debris field created by all losses of both sides until the given round.
+///
+/// This works the same as `BattleEngine::calculateDebris` in PHP: the losses of both sides are
+/// combined per unit type, after which the debris percentage is applied and rounded down per unit
+/// type and resource.
+pub fn calculate_debris(
+    attackers: &[BattleParticipant],
+    defenders: &[BattleParticipant],
+    attacker_rounds: &[ParticipantRound],
+    defender_rounds: &[ParticipantRound],
+    settings: &DebrisSettings,
+    rules: &BattleRules,
+) -> Resources {
+    // Combine losses of both sides per unit type.
+    let mut lost_amounts: HashMap<DebrisGroup, u64> = HashMap::new();
+    let sides = [(attackers, attacker_rounds), (defenders, defender_rounds)];
+    for (participants, participant_rounds) in sides {
+        for (participant, participant_round) in participants.iter().zip(participant_rounds) {
+            for (unit_id, lost) in &participant_round.losses {
+                // Units without cost are rejected by validation when debris settings are provided.
+                let Some(unit) = participant.units.get(unit_id) else { continue };
+                let Some(cost) = unit.cost.clone() else { continue };
+
+                let group = DebrisGroup {
+                    unit_id: *unit_id,
+                    cost,
+                    unit_type: unit.resolve_unit_type(rules),
+                };
+                *lost_amounts.entry(group).or_insert(0) += lost.amount as u64;
+            }
+        }
+    }
+
+    let mut debris = Resources::default();
+    for (group, amount) in lost_amounts {
+        let percentage = match group.unit_type {
+            UnitType::Ship => settings.from_ships_percentage,
+            UnitType::Defense => settings.from_defense_percentage,
+        };
+        if percentage <= 0.0 {
+            continue;
+        }
+
+        debris.metal = debris.metal.saturating_add(debris_amount(group.cost.metal, amount, percentage));
+        debris.crystal = debris.crystal.saturating_add(debris_amount(group.cost.crystal, amount, percentage));
+        if settings.deuterium_on {
+            debris.deuterium = debris.deuterium.saturating_add(debris_amount(group.cost.deuterium, amount, percentage));
+        }
+    }
+
+    debris
+}
+
+/// Calculate the debris of a single resource, rounded down. The order of operations matches PHP.
+///
+/// The value of the losses is capped at `u64::MAX` instead of overflowing for absurdly high costs.
+fn debris_amount(cost: u64, amount: u64, percentage: f64) -> u64 {
+    (cost.saturating_mul(amount) as f64 * (percentage / 100.0)).floor() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn debris_percentages_are_applied_per_unit_type() {
+        let attackers: Vec<BattleParticipant> = serde_json::from_str(
+            r#"[{"participant_id": 1, "units": {
+                "206": {"unit_id": 206, "amount": 10, "cost": {"metal": 20000, "crystal": 7000, "deuterium": 2000}, "rapidfire": {}}
+            }}]"#,
+        )
+        .unwrap();
+        let defenders: Vec<BattleParticipant> = serde_json::from_str(
+            r#"[{"participant_id": 2, "units": {
+                "204": {"unit_id": 204, "amount": 10, "cost": {"metal": 3000, "crystal": 1001, "deuterium": 0}, "rapidfire": {}},
+                "401": {"unit_id": 401, "amount": 20, "cost": {"metal": 2000, "crystal": 0, "deuterium": 0}, "rapidfire": {}}
+            }}]"#,
+        )
+        .unwrap();
+        let attacker_rounds: Vec<ParticipantRound> = serde_json::from_str(
+            r#"[{"participant_id": 1, "ships": {}, "losses_in_round": {}, "losses": {
+                "206": {"unit_id": 206, "amount": 3}
+            }}]"#,
+        )
+        .unwrap();
+        let defender_rounds: Vec<ParticipantRound> = serde_json::from_str(
+            r#"[{"participant_id": 2, "ships": {}, "losses_in_round": {}, "losses": {
+                "204": {"unit_id": 204, "amount": 7},
+                "401": {"unit_id": 401, "amount": 11}
+            }}]"#,
+        )
+        .unwrap();
+        let debris = |settings: &DebrisSettings| {
+            calculate_debris(&attackers, &defenders, &attacker_rounds, &defender_rounds, settings, &BattleRules::default())
+        };
+
+        // 30% of the ships only: 3 cruisers and 7 light fighters, rounded down per unit type.
+        let result = debris(&DebrisSettings::default());
+        assert_eq!((result.metal, result.crystal, result.deuterium), (18_000 + 6_300, 6_300 + 2_102, 0));
+
+        // 50% of the 11 rocket launchers as well, with deuterium.
+        let result = debris(&DebrisSettings {
+            from_ships_percentage: 30.0,
+            from_defense_percentage: 50.0,
+            deuterium_on: true,
+        });
+        assert_eq!((result.metal, result.crystal, result.deuterium), (18_000 + 6_300 + 11_000, 6_300 + 2_102, 1_800));
+
+        let result = debris(&DebrisSettings {
+            from_ships_percentage: 0.0,
+            from_defense_percentage: 0.0,
+            deuterium_on: true,
+        });
+        assert_eq!(result, Resources::default());
+    }
+
+    #[test]
+    fn debris_of_very_expensive_units_is_capped() {
+        let attackers: Vec<BattleParticipant> = serde_json::from_str(
+            r#"[{"participant_id": 1, "units": {
+                "204": {"unit_id": 204, "amount": 3, "cost": {"metal": 18446744073709551615, "crystal": 0, "deuterium": 0}, "rapidfire": {}},
+                "206": {"unit_id": 206, "amount": 3, "cost": {"metal": 18446744073709551615, "crystal": 0, "deuterium": 0}, "rapidfire": {}}
+            }}]"#,
+        )
+        .unwrap();
+        let attacker_rounds: Vec<ParticipantRound> = serde_json::from_str(
+            r#"[{"participant_id": 1, "ships": {}, "losses_in_round": {}, "losses": {
+                "204": {"unit_id": 204, "amount": 3},
+                "206": {"unit_id": 206, "amount": 3}
+            }}]"#,
+        )
+        .unwrap();
+        let settings = DebrisSettings {
+            from_ships_percentage: 100.0,
+            from_defense_percentage: 0.0,
+            deuterium_on: false,
+        };
+
+        let result = calculate_debris(&attackers, &[], &attacker_rounds, &[], &settings, &BattleRules::default());
+        assert_eq!(result.metal, u64::MAX);
+    }
+}
@@ -0,0 +1,148 @@
+use crate::format::WireFormat;
+use serde::{Deserialize, Serialize};
+use std::any::Any;
+
+/// Error codes which can be returned to the PHP client instead of the battle output.
+///
+/// The codes are serialized in snake_case so the PHP client can match on them directly.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BattleErrorCode {
+    /// A null pointer was passed to the FFI interface.
+    NullPointer,
+    /// The input string is not valid UTF-8.
+    InvalidUtf8,
+    /// The input string is not valid JSON or does not match the battle input structure.
+    InvalidJson,
+    /// The input buffer is not valid MessagePack or does not match the battle input structure.
+    InvalidMessagePack,
+    /// The input is structurally valid but contains values the engine cannot process.
+    InvalidInput,
+    /// The estimated memory usage of the battle exceeds the memory budget, even with aggregated units.
+    BattleTooLarge,
+    /// The battle output could not be serialized.
+    Serialization,
+    /// The engine panicked while processing the battle. This is always a bug in the engine.
+    Panic,
+}
+
+/// Error which is returned to the PHP client when a battle could not be processed.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BattleError {
+    /// Machine-readable error code.
+    pub code: BattleErrorCode,
+    /// Human-readable description of what went wrong.
+    pub message: String,
+    /// Path of the offending input field (e.g. `attacker_units.204.amount`) if the error
+    /// can be attributed to a single field.
+    pub field: Option<String>,
+    /// All problems found during input validation. Only set for `invalid_input` errors.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub issues: Vec<ValidationIssue>,
+}
+
+/// Single problem found while validating the battle input.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ValidationIssue {
+    /// Path of the offending input field, e.g. `defender_units.401.hull_plating`.
+    pub field: String,
+    /// Description of the problem.
+    pub message: String,
+}
+
+impl ValidationIssue {
+    /// Create a new validation issue for the given field path.
+    pub fn new(field: impl Into<String>, message: impl Into<String>) -> Self {
+        ValidationIssue {
+            field: field.into(),
+            message: message.into(),
+        }
+    }
+}
+
+/// Envelope which wraps the error so the PHP client can distinguish it from a battle output
+/// by checking for the `error` key.
+#[derive(Serialize)]
+struct BattleErrorEnvelope<'a> {
+    error: &'a BattleError,
+}
+
+impl BattleError {
+    /// Create a new battle error without an offending field.
+    pub fn new(code: BattleErrorCode, message: impl Into<String>) -> Self {
+        BattleError {
+            code,
+            message: message.into(),
+            field: None,
+            issues: Vec::new(),
+        }
+    }
+
+    /// Attach the path of the offending input field to the error.
+    pub fn with_field(mut self, field: impl Into<String>) -> Self {
+        self.field = Some(field.into());
+        self
+    }
+
+    /// Create a battle error from a list of validation issues.
+    ///
+    /// The first issue is used as the offending field of the error, all issues are included in
+    /// the `issues` list.
+    pub fn from_issues(issues: Vec<ValidationIssue>) -> Self {
+        let message = format!("battle input failed validation with {} issue(s)", issues.len());
+        let mut error = BattleError::new(BattleErrorCode::InvalidInput, message);
+        error.field = issues.first().map(|issue| issue.field.clone());
+        error.issues = issues;
+        error
+    }
+
+    /// Create a battle error from the payload of a caught panic.
+    pub fn from_panic(payload: Box<dyn Any + Send>) -> Self {
+        let message = if let Some(message) = payload.downcast_ref::<&str>() {
+            message.to_string()
+        } else if let Some(message) = payload.downcast_ref::<String>() {
+            message.clone()
+        } else {
+            "unknown panic".to_string()
+        };
+
+        BattleError::new(BattleErrorCode::Panic, format!("battle engine panicked: {}", message))
+    }
+
+    /// Create a battle error from a deserialization error with the path of the offending field.
+    pub fn from_path_error<E: std::fmt::Display>(error: serde_path_to_error::Error<E>, code: BattleErrorCode) -> Self {
+        let path = error.path().to_string();
+        let error = BattleError::new(code, error.into_inner().to_string());
+
+        // serde_path_to_error uses "." for the root path, which is not a useful field.
+        if path == "." {
+            error
+        } else {
+            error.with_field(path)
+        }
+    }
+
+    /// Serialize the error into the error envelope in the given format, which is returned to the PHP
+    /// client instead of the battle output.
+    pub fn encode(&self, format: WireFormat) -> Vec<u8> {
+        match format {
+            WireFormat::Json => self.to_json().into_bytes(),
+            WireFormat::MessagePack => rmp_serde::to_vec_named(&BattleErrorEnvelope { error: self })
+                .unwrap_or_else(|_| self.to_json().into_bytes()),
+        }
+    }
+
+    /// Serialize the error into the JSON error envelope which is returned to the PHP client.
+    pub fn to_json(&self) -> String {
+        // Serializing plain strings and enums can't fail, but never panic on the error path.
+        serde_json::to_string(&BattleErrorEnvelope { error: self }).unwrap_or_else(|_| {
+            r#"{"error":{"code":"serialization","message":"failed to serialize error","field":null}}"#.to_string()
+        })
+    }
+}
+
+impl From<serde_path_to_error::Error<serde_json::Error>> for BattleError {
+    fn from(error: serde_path_to_error::Error<serde_json::Error>) -> Self {
+        BattleError::from_path_error(error, BattleErrorCode::InvalidJson)
+    }
+}
@@ -0,0 +1,400 @@
+//! Expected-value estimate of a battle.
+//!
+//! Instead of simulating every shot, every round is modelled analytically per unit group (units of the
+//! same type of a single participant):
+//!
+//! 1. Every shooting group fires at the groups of the other side, with a chance proportional to the
+//!    amount of units in the group. Rapidfire chains are modelled as a geometric series: a unit fires
+//!    `1 / (1 - q)` shots on average, where `q` is the chance to fire again after a shot.
+//! 2. The hits a single unit takes are Poisson distributed. For every amount of hits the shield
+//!    absorption, hull damage and explosion chance are calculated with the average damage per hit,
+//!    which gives the expected amount of destroyed units and the average health of the survivors.
+//!
+//! The expected amounts are rounded to whole units in the battle rounds. Random events after the
+//! battle (defense repairs and the moon creation) are not estimated.
+use crate::flee::{self, FledUnits};
+use crate::rules::{RuleSet, MAX_RAPIDFIRE};
+use crate::{
+    calculate_losses, calculate_round_debris, determine_outcome, increment_battle_unit_count_amount,
+    merge_participant_counts, new_battle_round, new_side_result, normalize_participants, record_hits, BattleInput,
+    BattleOutcome, BattleParticipant, BattleRound, BattleRules, BattleUnitCount, BattleUnitInfo, ParticipantRound,
+    Resources, SideResult,
+};
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// Upper limit for the amount of hits a single unit is modelled to take in a round. Units taking more
+/// hits are considered destroyed.
+const MAX_HITS_PER_UNIT: usize = 1_000_000;
+
+/// Estimated battle output which is returned to the PHP client.
+#[derive(Serialize)]
+pub struct BattleEstimate {
+    /// Always true, so the PHP client can tell an estimate apart from a simulated battle.
+    estimated: bool,
+    outcome: BattleOutcome,
+    rounds_fought: u32,
+    attacker: SideResult,
+    defender: SideResult,
+    /// Ships of the defender that fled before the battle, the same as for a simulated battle.
+    fled_units: FledUnits,
+    rounds: Vec<BattleRound>,
+    /// Expected debris field. Only set when debris settings are provided.
+    debris: Option<Resources>,
+}
+
+/// Units of the same type of a single participant, with the expected amount and average health.
+struct EstimateGroup<'a> {
+    participant_idx: usize,
+    info: &'a BattleUnitInfo,
+    amount: f64,
+    current_shield_points: f64,
+    current_hull_plating: f64,
+}
+
+/// Expected hits on a group in a single combat phase.
+#[derive(Clone, Default)]
+struct IncomingHits {
+    hits: f64,
+    damage: f64,
+}
+
+/// Expected result of the hits on a single unit.
+struct HitOutcome {
+    /// Chance that the unit survives.
+    survival: f64,
+    /// Average shield points of the unit if it survives.
+    current_shield_points: f64,
+    /// Average hull plating of the unit if it survives.
+    current_hull_plating: f64,
+    /// Expected damage absorbed by the shield of the unit.
+    absorbed_damage: f64,
+}
+
+/// Estimate the battle rounds with expected values instead of simulating every shot.
+pub fn estimate_battle_rounds(mut input: BattleInput) -> BattleEstimate {
+    let mut rounds: Vec<BattleRound> = Vec::new();
+    let mut debris = input.debris.as_ref().map(|_| Resources::default());
+
+    // Treat single fleets as a single participant so the rest of the engine only deals with participants.
+    normalize_participants(&mut input);
+    let fled_units = flee::flee_before_combat(&mut input);
+
+    let mut attackers = new_groups(&input.attackers);
+    let mut defenders = new_groups(&input.defenders);
+
+    for _ in 0..input.rules.max_rounds {
+        if !has_units(&attackers) || !has_units(&defenders) {
+            break;
+        }
+
+        let mut round = new_battle_round(&input);
+
+        // Both sides fire with the units at the start of the round, as destroyed units are only
+        // removed at the end of the round.
+        let hits_on_defenders = incoming_hits(&attackers, &defenders, &input.rules, input.rule_set);
+        let hits_on_attackers = incoming_hits(&defenders, &attackers, &input.rules, input.rule_set);
+        let (hits, full_strength, absorbed_damage) = apply_hits(&mut defenders, &hits_on_defenders, &input.rules, input.rule_set);
+        record_hits(&mut round, true, hits, full_strength, absorbed_damage);
+        let (hits, full_strength, absorbed_damage) = apply_hits(&mut attackers, &hits_on_attackers, &input.rules, input.rule_set);
+        record_hits(&mut round, false, hits, full_strength, absorbed_damage);
+
+        // Update round statistics
+        let previous_round = rounds.last();
+        count_groups(&attackers, &mut round.attacker_participants);
+        count_groups(&defenders, &mut round.defender_participants);
+        calculate_losses_in_round(
+            &input.attackers,
+            previous_round.map(|round| &round.attacker_participants[..]),
+            &mut round.attacker_participants,
+        );
+        calculate_losses_in_round(
+            &input.defenders,
+            previous_round.map(|round| &round.defender_participants[..]),
+            &mut round.defender_participants,
+        );
+        round.attacker_ships = merge_participant_counts(&round.attacker_participants, |participant| &participant.ships);
+        round.defender_ships = merge_participant_counts(&round.defender_participants, |participant| &participant.ships);
+        round.attacker_losses_in_round = merge_participant_counts(&round.attacker_participants, |participant| &participant.losses_in_round);
+        round.defender_losses_in_round = merge_participant_counts(&round.defender_participants, |participant| &participant.losses_in_round);
+
+        // Calculate accumulated losses
+        calculate_losses(&mut round, &input.attackers, &input.defenders);
+
+        // Calculate the debris created in this round
+        calculate_round_debris(&mut round, &input, &mut debris);
+
+        rounds.push(round);
+    }
+
+    // Summarize the battle for each side.
+    let (attacker_rounds, defender_rounds) = match rounds.last() {
+        Some(last_round) => (&last_round.attacker_participants[..], &last_round.defender_participants[..]),
+        None => (&[][..], &[][..]),
+    };
+    let attacker = new_side_result(count_unit_types(&attackers), &input.attackers, attacker_rounds);
+    let defender = new_side_result(count_unit_types(&defenders), &input.defenders, defender_rounds);
+    let outcome = determine_outcome(rounds.len(), has_units(&attackers), has_units(&defenders));
+
+    BattleEstimate {
+        estimated: true,
+        outcome,
+        rounds_fought: rounds.len() as u32,
+        attacker,
+        defender,
+        fled_units,
+        rounds,
+        debris,
+    }
+}
+
+/// Create the unit groups of one side of the battle, in order of participant and unit id.
+fn new_groups(participants: &[BattleParticipant]) -> Vec<EstimateGroup<'_>> {
+    let mut groups = Vec::new();
+    for (participant_idx, participant) in participants.iter().enumerate() {
+        let mut sorted_units: Vec<&BattleUnitInfo> = participant.units.values().collect();
+        sorted_units.sort_by_key(|unit| unit.unit_id);
+
+        for info in sorted_units {
+            groups.push(EstimateGroup {
+                participant_idx,
+                info,
+                amount: info.amount as f64,
+                current_shield_points: info.shield_points() as f64,
+                current_hull_plating: info.hull_plating() as f64,
+            });
+        }
+    }
+
+    groups
+}
+
+/// Whether a side has any units left. Groups are emptied when less than half a unit is left.
+fn has_units(groups: &[EstimateGroup]) -> bool {
+    groups.iter().any(|group| group.amount > 0.0)
+}
+
+/// Calculate the expected hits of all shooting groups on every target group.
+fn incoming_hits(
+    shooters: &[EstimateGroup],
+    targets: &[EstimateGroup],
+    rules: &BattleRules,
+    rule_set: RuleSet,
+) -> Vec<IncomingHits> {
+    let mut incoming = vec![IncomingHits::default(); targets.len()];
+    let target_count: f64 = targets.iter().map(|target| target.amount).sum();
+    if target_count <= 0.0 {
+        return incoming;
+    }
+
+    let bounce_factor = rules.shield_bounce_percentage as f64 / 100.0;
+    for shooter in shooters.iter().filter(|shooter| shooter.amount > 0.0) {
+        let damage = shooter.info.attack_power() as f64;
+        let bounces = |target: &EstimateGroup| damage < bounce_factor * target.info.shield_points() as f64;
+
+        // Chance to fire again after a shot. With the legacy rule set a bounced shot ends the rapidfire
+        // chain. Targets are modelled with full shields, so shots bounce the same with both rule sets.
+        // Capped at the chance of the maximum rapidfire, so every unit fires a finite amount of shots.
+        let continue_chance: f64 = targets
+            .iter()
+            .filter(|target| !(rule_set.bounce_ends_rapidfire() && bounces(target)))
+            .filter_map(|target| {
+                let rapidfire_amount = shooter.info.rapidfire.get(&target.info.unit_id)?;
+                Some(target.amount / target_count * rule_set.rapidfire_chance(*rapidfire_amount) / 100.0)
+            })
+            .sum::<f64>()
+            .min(rule_set.rapidfire_chance(MAX_RAPIDFIRE) / 100.0);
+        let shots_per_unit = 1.0 / (1.0 - continue_chance);
+
+        for (target, incoming) in targets.iter().zip(incoming.iter_mut()) {
+            if bounces(target) {
+                continue;
+            }
+
+            let hits = shooter.amount * shots_per_unit * target.amount / target_count;
+            incoming.hits += hits;
+            incoming.damage += hits * damage;
+        }
+    }
+
+    incoming
+}
+
+/// Apply the expected hits to the target groups and regenerate the shields of the survivors.
+///
+/// Returns the total amount of hits, their total damage and the damage absorbed by shields.
+fn apply_hits(
+    targets: &mut [EstimateGroup],
+    incoming: &[IncomingHits],
+    rules: &BattleRules,
+    rule_set: RuleSet,
+) -> (u32, f64, f64) {
+    let explosion_factor = rules.explosion_hull_percentage as f64 / 100.0;
+    let regeneration_factor = rules.shield_regeneration_percentage as f64 / 100.0;
+
+    let mut hits = 0.0;
+    let mut full_strength = 0.0;
+    let mut absorbed_damage = 0.0;
+    for (target, incoming) in targets.iter_mut().zip(incoming) {
+        if target.amount > 0.0 && incoming.hits > 0.0 {
+            let outcome = expected_hit_outcome(
+                incoming.hits / target.amount,
+                incoming.damage / incoming.hits,
+                target,
+                explosion_factor,
+                rule_set,
+            );
+
+            hits += incoming.hits;
+            full_strength += incoming.damage;
+            absorbed_damage += outcome.absorbed_damage * target.amount;
+
+            target.amount *= outcome.survival;
+            target.current_shield_points = outcome.current_shield_points;
+            target.current_hull_plating = outcome.current_hull_plating;
+        }
+
+        // Less than half a unit is rounded down to no units at all.
+        if target.amount < 0.5 {
+            target.amount = 0.0;
+        }
+
+        let shield_points = target.info.shield_points() as f64;
+        target.current_shield_points = (target.current_shield_points + shield_points * regeneration_factor).min(shield_points);
+    }
+
+    (hits.round() as u32, full_strength, absorbed_damage)
+}
+
+/// Calculate the expected result for a unit of the target group which takes a Poisson distributed
+/// amount of hits with the given average, every hit doing the given damage.
+fn expected_hit_outcome(
+    average_hits: f64,
+    damage: f64,
+    target: &EstimateGroup,
+    explosion_factor: f64,
+    rule_set: RuleSet,
+) -> HitOutcome {
+    let hull_plating = target.info.hull_plating() as f64;
+    let average_hits = if average_hits.is_finite() { average_hits.min(MAX_HITS_PER_UNIT as f64) } else { MAX_HITS_PER_UNIT as f64 };
+
+    // Only amounts of hits with a relevant probability are added up.
+    let deviation = average_hits.sqrt();
+    let min_hits = (average_hits - 10.0 * deviation).floor().max(0.0) as usize;
+    let max_hits = ((average_hits + 10.0 * deviation).ceil() as usize).saturating_add(10).min(MAX_HITS_PER_UNIT);
+
+    // State of the unit after the current amount of hits.
+    let mut current_shield_points = target.current_shield_points;
+    let mut current_hull_plating = target.current_hull_plating;
+    let mut absorbed_damage = 0.0;
+    let mut survival = 1.0;
+
+    let mut log_probability = -average_hits;
+    let mut probability_sum = 0.0;
+    let mut outcome = HitOutcome {
+        survival: 0.0,
+        current_shield_points: 0.0,
+        current_hull_plating: 0.0,
+        absorbed_damage: 0.0,
+    };
+    for hits in 0..=max_hits {
+        if hits > 0 {
+            log_probability += average_hits.ln() - (hits as f64).ln();
+
+            // Apply damage to shields first, then hull plating, the same as `apply_shot`.
+            let hull_damaged = damage > current_shield_points;
+            if current_shield_points > 0.0 {
+                if damage <= current_shield_points {
+                    absorbed_damage += damage;
+                    current_shield_points -= damage;
+                } else {
+                    absorbed_damage += current_shield_points;
+                    current_hull_plating -= damage - current_shield_points;
+                    current_shield_points = 0.0;
+                }
+            } else {
+                current_hull_plating -= damage;
+            }
+
+            // Chance to explode when the hull integrity is below 70% (by default), which is rolled
+            // with a dice from 0 to 100. The official rule set only rolls when the hull was damaged.
+            let hull_integrity = current_hull_plating / hull_plating;
+            let can_explode = hull_damaged || rule_set.explodes_without_hull_damage();
+            if current_hull_plating <= 0.0 {
+                survival = 0.0;
+            } else if can_explode && hull_integrity < explosion_factor {
+                let explosion_chance = ((100.0 - hull_integrity * 100.0) as i32).clamp(0, 101);
+                survival *= 1.0 - explosion_chance as f64 / 101.0;
+            }
+        }
+
+        if hits >= min_hits {
+            let probability = log_probability.exp();
+            probability_sum += probability;
+            outcome.survival += probability * survival;
+            outcome.current_shield_points += probability * survival * current_shield_points;
+            outcome.current_hull_plating += probability * survival * current_hull_plating;
+            outcome.absorbed_damage += probability * absorbed_damage;
+        }
+
+        // A destroyed unit stays destroyed and does not absorb any more damage.
+        if survival < 1e-12 {
+            outcome.absorbed_damage += (1.0 - probability_sum).max(0.0) * absorbed_damage;
+            break;
+        }
+    }
+
+    if outcome.survival > 0.0 {
+        outcome.current_shield_points /= outcome.survival;
+        outcome.current_hull_plating /= outcome.survival;
+    }
+
+    outcome
+}
+
+/// Count the expected units, rounded to whole units, per participant and unit type into the `ships`
+/// of the participant rounds.
+fn count_groups(groups: &[EstimateGroup], participant_rounds: &mut [ParticipantRound]) {
+    for group in groups {
+        let amount = group.amount.round() as u32;
+        if amount > 0 {
+            let ships = &mut participant_rounds[group.participant_idx].ships;
+            ships.insert(group.info.unit_id, BattleUnitCount { unit_id: group.info.unit_id, amount });
+        }
+    }
+}
+
+/// Count the expected units, rounded to whole units, per unit type over all participants.
+fn count_unit_types(groups: &[EstimateGroup]) -> HashMap<i16, BattleUnitCount> {
+    let mut counts = HashMap::new();
+    for group in groups {
+        let amount = group.amount.round() as u32;
+        if amount > 0 {
+            increment_battle_unit_count_amount(&mut counts, group.info.unit_id, amount);
+        }
+    }
+
+    counts
+}
+
+/// Calculate the losses in this round per participant by comparing the units with the units at the
+/// end of the previous round, or with the starting units for the first round.
+fn calculate_losses_in_round(
+    participants: &[BattleParticipant],
+    previous_rounds: Option<&[ParticipantRound]>,
+    participant_rounds: &mut [ParticipantRound],
+) {
+    for (participant_idx, (participant, participant_round)) in participants.iter().zip(participant_rounds.iter_mut()).enumerate() {
+        for unit in participant.units.values() {
+            let previous_count = match previous_rounds {
+                Some(previous_rounds) => previous_rounds[participant_idx].ships.get(&unit.unit_id).map_or(0, |unit| unit.amount),
+                None => unit.amount,
+            };
+            let current_count = participant_round.ships.get(&unit.unit_id).map_or(0, |unit| unit.amount);
+
+            if current_count < previous_count {
+                increment_battle_unit_count_amount(&mut participant_round.losses_in_round, unit.unit_id, previous_count - current_count);
+            }
+        }
+    }
+}
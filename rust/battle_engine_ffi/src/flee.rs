@@ -0,0 +1,116 @@
+use crate::{increment_battle_unit_count_amount, BattleInput, BattleParticipant, BattleUnitCount, UnitType};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Flee settings which are provided by the PHP client.
+///
+/// When provided, the ships of the defender flee before the battle starts if the attacker is much
+/// stronger. Defenses can't flee and always stay to fight.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct FleeSettings {
+    /// The ships of the defender flee when the strength of the attacker is at least this many times
+    /// the strength of the defender.
+    pub ratio: f64,
+    /// How the strength of a side is calculated.
+    pub strength: FleeStrength,
+}
+
+impl Default for FleeSettings {
+    fn default() -> Self {
+        FleeSettings {
+            ratio: 5.0,
+            strength: FleeStrength::Cost,
+        }
+    }
+}
+
+/// How the strength of a side is calculated for the flee check. The strength includes all units of
+/// the side, defenses as well.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FleeStrength {
+    /// Total resource cost of the units, which requires the `cost` of every unit to be set.
+    #[default]
+    Cost,
+    /// Total attack power, shield points and hull plating of the units.
+    CombatValue,
+}
+
+/// Units of a single defending participant which fled before the battle.
+#[derive(Serialize, Deserialize)]
+pub struct FledParticipant {
+    participant_id: u64,
+    units: HashMap<i16, BattleUnitCount>,
+}
+
+/// Units of the defender which fled before the battle. They don't take part in the battle, so they are
+/// neither surviving nor lost units.
+#[derive(Serialize, Deserialize, Default)]
+pub struct FledUnits {
+    /// Fled units of all defending participants together.
+    pub units: HashMap<i16, BattleUnitCount>,
+    /// Fled units per defending participant, in the same order as the defending participants.
+    pub participants: Vec<FledParticipant>,
+}
+
+/// Let the ships of the defender flee when the attacker is much stronger, which removes them from the
+/// battle input. Does nothing without flee settings.
+///
+/// The participants must be normalized, so the strength is based on the effective stats of the units.
+pub fn flee_before_combat(input: &mut BattleInput) -> FledUnits {
+    let Some(settings) = &input.flee else {
+        return FledUnits::default();
+    };
+
+    let attacker_strength = side_strength(&input.attackers, settings.strength);
+    let defender_strength = side_strength(&input.defenders, settings.strength);
+    if defender_strength == 0.0 || attacker_strength < settings.ratio * defender_strength {
+        return FledUnits::default();
+    }
+
+    let mut fled_units = FledUnits::default();
+    for participant in &mut input.defenders {
+        let mut units = HashMap::new();
+        participant.units.retain(|_, unit| {
+            if unit.resolve_unit_type(&input.rules) == UnitType::Defense {
+                return true;
+            }
+
+            increment_battle_unit_count_amount(&mut units, unit.unit_id, unit.amount);
+            increment_battle_unit_count_amount(&mut fled_units.units, unit.unit_id, unit.amount);
+            false
+        });
+
+        fled_units.participants.push(FledParticipant {
+            participant_id: participant.participant_id,
+            units,
+        });
+    }
+
+    fled_units
+}
+
+/// Strength of all units of one side.
+fn side_strength(participants: &[BattleParticipant], strength: FleeStrength) -> f64 {
+    // Sort by unit id so the strength is added up in the same order for every battle.
+    participants
+        .iter()
+        .flat_map(|participant| {
+            let mut units: Vec<_> = participant.units.values().collect();
+            units.sort_by_key(|unit| unit.unit_id);
+            units
+        })
+        .map(|unit| {
+            let unit_strength = match strength {
+                FleeStrength::Cost => unit
+                    .cost
+                    .as_ref()
+                    .map_or(0.0, |cost| cost.metal as f64 + cost.crystal as f64 + cost.deuterium as f64),
+                FleeStrength::CombatValue => (unit.attack_power() + unit.shield_points() + unit.hull_plating()) as f64,
+            };
+
+            unit_strength * unit.amount as f64
+        })
+        .sum()
+}
@@ -0,0 +1,90 @@
+use crate::error::{BattleError, BattleErrorCode};
+use serde::de::{self, MapAccess, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+use std::marker::PhantomData;
+
+/// Encoding of the battle input and output, selected by the format flag of `fight_battle_rounds_encoded`.
+///
+/// Both formats use the same schema: MessagePack maps have the same keys as the JSON objects, except
+/// that unit ids are encoded as integer keys instead of strings. PHP decodes both into the same array.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WireFormat {
+    Json,
+    MessagePack,
+}
+
+impl WireFormat {
+    /// Get the format for the format flag of the FFI call: 0 for JSON and 1 for MessagePack.
+    pub fn from_flag(flag: u32) -> Option<Self> {
+        match flag {
+            0 => Some(WireFormat::Json),
+            1 => Some(WireFormat::MessagePack),
+            _ => None,
+        }
+    }
+
+    /// Error code for input that can't be decoded in this format.
+    pub fn decode_error_code(self) -> BattleErrorCode {
+        match self {
+            WireFormat::Json => BattleErrorCode::InvalidJson,
+            WireFormat::MessagePack => BattleErrorCode::InvalidMessagePack,
+        }
+    }
+
+    /// Encode a value in this format.
+    pub fn serialize<T: Serialize>(self, value: &T) -> Result<Vec<u8>, BattleError> {
+        let output = match self {
+            WireFormat::Json => serde_json::to_vec(value).map_err(|error| error.to_string()),
+            // Structs are encoded as maps with field names to keep the schema the same as the JSON.
+            WireFormat::MessagePack => rmp_serde::to_vec_named(value).map_err(|error| error.to_string()),
+        };
+
+        output.map_err(|message| BattleError::new(BattleErrorCode::Serialization, message))
+    }
+}
+
+/// Deserialize a map which may also be encoded as an empty list.
+///
+/// PHP can't tell an empty map from an empty list, so `msgpack_pack` encodes an empty array, e.g. a
+/// unit without rapidfire, as an empty list. The JSON input is encoded with `JSON_FORCE_OBJECT`, which
+/// doesn't have this problem.
+pub fn deserialize_map<'de, D, K, V>(deserializer: D) -> Result<HashMap<K, V>, D::Error>
+where
+    D: Deserializer<'de>,
+    K: Deserialize<'de> + Eq + Hash,
+    V: Deserialize<'de>,
+{
+    struct MapVisitor<K, V>(PhantomData<(K, V)>);
+
+    impl<'de, K, V> Visitor<'de> for MapVisitor<K, V>
+    where
+        K: Deserialize<'de> + Eq + Hash,
+        V: Deserialize<'de>,
+    {
+        type Value = HashMap<K, V>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a map")
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+            let mut values = HashMap::with_capacity(map.size_hint().unwrap_or(0));
+            while let Some((key, value)) = map.next_entry()? {
+                values.insert(key, value);
+            }
+            Ok(values)
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+            match seq.next_element::<de::IgnoredAny>()? {
+                None => Ok(HashMap::new()),
+                Some(_) => Err(de::Error::invalid_type(de::Unexpected::Seq, &self)),
+            }
+        }
+    }
+
+    deserializer.deserialize_any(MapVisitor(PhantomData))
+}
@@ -8,31 +8,289 @@
 //! This battle engine is functionally equivalent to the OGameX PHP battle engine but is optimized
 //! for performance and memory usage. It is up to 200x faster than the equivalent PHP implementation
 //! and uses up to 10x less memory.
+//!
+//! ## Memory ownership
+//!
+//! Every string returned by this library is allocated by Rust and is owned by the caller until it
+//! is handed back to the matching free function exactly once:
+//!
+//! - `fight_battle_rounds` -> `free_battle_output`
+//! - `estimate_battle_rounds` -> `free_battle_output`
+//! - `fight_battles_batch` -> `free_battle_output`
+//! - `fight_battle_rounds_streaming` -> `free_battle_output`
+//! - `fight_battle_rounds_encoded` -> `free_battle_output_buffer`
+//! - `fight_battle_rounds_native` -> `free_battle_output_native`
+//!
+//! The caller must copy the string (e.g. with `FFI::string()` in PHP) before freeing it and must never
+//! release it with `libc::free` or any other allocator. Long-running PHP workers rely on this to keep
+//! their memory usage stable when processing thousands of battles.
+//!
+//! ## Errors
+//!
+//! The engine never panics across the FFI boundary. When a battle can't be processed the returned
+//! string contains an error envelope instead of the battle output:
+//!
+//! ```json
+//! {"error": {"code": "invalid_json", "message": "missing field `amount`", "field": "attacker_units.204"}}
+//! ```
+//!
+//! See `BattleErrorCode` for the possible error codes.
+mod arithmetic;
+mod bonuses;
+mod budget;
+mod batch;
+mod debris;
+mod error;
+mod estimate;
+mod flee;
+mod format;
+mod metrics;
+mod moon;
+mod native;
+mod parallel;
+mod rules;
+mod stats;
+mod units;
+mod validation;
+
 use serde::{Deserialize, Serialize};
 use std::ffi::{CStr, CString};
-use std::os::raw::c_char;
-use rand::Rng;
+use std::os::raw::{c_char, c_void};
+use std::panic::{self, AssertUnwindSafe};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
 use std::collections::HashMap;
 use memory_stats::memory_stats;
 
+pub use arithmetic::Arithmetic;
+pub use bonuses::{BonusTable, CombatBonus, Officer, PlayerClass, RapidfireBonus};
+pub use debris::DebrisSettings;
+pub use error::{BattleError, BattleErrorCode, ValidationIssue};
+pub use flee::{FleeSettings, FleeStrength};
+pub use format::WireFormat;
+pub use moon::MoonSettings;
+pub use native::{NativeBattleInput, NativeBattleOutput, NativeRapidfire, NativeRound, NativeUnit};
+pub use rules::{BattleRules, RuleSet};
+pub use stats::{BaseStats, Technologies};
+pub use units::UnitStrategy;
+
+use arithmetic::Points;
+use flee::FledUnits;
+use metrics::{CountingAllocator, MetricsRecorder};
+use stats::UnitStats;
+use units::{UnitGroup, UnitStore, UnitTypeIndex};
+
+/// Counts the allocations of the engine when metrics are requested, see `BattleInput::metrics`.
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Random number generator which is used for all dice rolls during a battle.
+///
+/// ChaCha8 is used because its output is guaranteed to be stable across platforms and rand
+/// versions, so a battle can be replayed bit for bit from its seed.
+type BattleRng = ChaCha8Rng;
+
 /// Battle input which is provided by the PHP client.
-#[derive(Serialize, Deserialize)]
+///
+/// The units of both sides can be provided either as a single fleet per side (`attacker_units` and
+/// `defender_units`) or as a list of participants per side (`attackers` and `defenders`) for ACS
+/// battles. A single fleet is treated as a single participant with id 0.
+#[derive(Serialize, Deserialize, Default)]
 pub struct BattleInput {
+    #[serde(default, deserialize_with = "format::deserialize_map")]
     attacker_units: HashMap<i16, BattleUnitInfo>,
+    #[serde(default, deserialize_with = "format::deserialize_map")]
     defender_units: HashMap<i16, BattleUnitInfo>,
+    /// Attacking participants (ACS attack). Can't be combined with `attacker_units`.
+    #[serde(default)]
+    attackers: Vec<BattleParticipant>,
+    /// Defending participants (planet owner and ACS defend fleets). Can't be combined with `defender_units`.
+    #[serde(default)]
+    defenders: Vec<BattleParticipant>,
+    /// Technologies of the attacker, used for the units of `attacker_units` with base stats.
+    #[serde(default)]
+    attacker_technologies: Technologies,
+    /// Technologies of the defender, used for the units of `defender_units` with base stats.
+    #[serde(default)]
+    defender_technologies: Technologies,
+    /// Combat bonuses of the player classes and officers of the participants. Defaults to the OGame
+    /// bonuses when omitted.
+    #[serde(default)]
+    bonus_table: BonusTable,
+    /// Reject input fields which are not known to the engine instead of silently ignoring them.
+    /// Used by the PHP tests to catch mistakes in the battle input early.
+    #[serde(default)]
+    strict: bool,
+    /// Seed for the random number generator. When omitted a random seed is generated. Providing the
+    /// seed of an earlier battle replays that battle exactly.
+    #[serde(default)]
+    seed: Option<u64>,
+    /// Combat rule parameters. Defaults to the OGame rules when omitted.
+    #[serde(default)]
+    rules: BattleRules,
+    /// Set of combat details, `ogamex_legacy` (default) or `ogame_official`, see `RuleSet`.
+    #[serde(default)]
+    rule_set: RuleSet,
+    /// Arithmetic for shield points, hull plating and damage, `float` (default) or `integer`. Use
+    /// `integer` for damage and shield absorption that match the PHP engine exactly.
+    #[serde(default)]
+    arithmetic: Arithmetic,
+    /// Debris field settings. When provided the engine calculates the debris field, which requires
+    /// the `cost` of every unit to be set.
+    #[serde(default)]
+    debris: Option<DebrisSettings>,
+    /// Moon settings. When provided the engine calculates the moon chance and rolls for the moon
+    /// creation, which requires debris settings to be provided as well.
+    #[serde(default)]
+    moon: Option<MoonSettings>,
+    /// Flee settings. When provided the ships of the defender flee before the battle if the attacker
+    /// is much stronger.
+    #[serde(default)]
+    flee: Option<FleeSettings>,
+    /// How units are stored during the battle. Use `aggregated` for battles with millions of units.
+    #[serde(default)]
+    unit_strategy: UnitStrategy,
+    /// Amount of threads used to process the combat phases. When omitted the `BATTLE_ENGINE_THREADS`
+    /// environment variable is used, which defaults to 1. A battle is reproducible for a given seed and
+    /// amount of threads, but multi-threaded battles differ from single-threaded ones.
+    #[serde(default)]
+    threads: Option<usize>,
+    /// Maximum estimated memory usage of the battle in kilobytes. When omitted the
+    /// `BATTLE_ENGINE_MEMORY_BUDGET` environment variable is used, without it memory usage is not
+    /// limited. Battles which don't fit are processed with aggregated units, or rejected with a
+    /// `battle_too_large` error if they don't fit with aggregated units either.
+    #[serde(default)]
+    memory_budget: Option<u64>,
+    /// Return allocation, timing and shot metrics of the engine as `metrics` in the battle output.
+    #[serde(default)]
+    metrics: bool,
+}
+
+/// Battle participant which is provided by the PHP client.
+///
+/// A participant is a single fleet taking part in the battle, e.g. one of the fleets of an ACS attack.
+/// Every participant has its own unit stats, as the stats depend on the research levels of its owner.
+#[derive(Serialize, Deserialize, Clone)]
+struct BattleParticipant {
+    /// Id of the participant as known by the PHP client, e.g. the fleet mission id.
+    participant_id: u64,
+    #[serde(deserialize_with = "format::deserialize_map")]
+    units: HashMap<i16, BattleUnitInfo>,
+    /// Technologies of the owner, used for the units with base stats.
+    #[serde(default)]
+    technologies: Technologies,
 }
 
 /// Battle unit info which is provided by the PHP client.
 ///
-/// This contains static information about the input units and their amount.
+/// This contains static information about the input units and their amount. The effective stats are
+/// either provided directly or derived from `base_stats` and the technologies of the participant.
 #[derive(Serialize, Deserialize, Clone)]
 struct BattleUnitInfo {
     unit_id: i16,
     amount: u32,
-    attack_power: f32,
-    shield_points: f32,
-    hull_plating: f32,
+    #[serde(default)]
+    attack_power: Option<f32>,
+    #[serde(default)]
+    shield_points: Option<f32>,
+    #[serde(default)]
+    hull_plating: Option<f32>,
+    /// Base stats of the unit. Can't be combined with the effective stats.
+    #[serde(default)]
+    base_stats: Option<BaseStats>,
+    #[serde(deserialize_with = "format::deserialize_map")]
     rapidfire: HashMap<i16, u16>,
+    /// Resource cost of a single unit. Used for the debris field calculation.
+    #[serde(default)]
+    cost: Option<Resources>,
+    /// Whether the unit is a ship or a defense. When omitted the defense unit id range of the
+    /// combat rules is used.
+    #[serde(default)]
+    unit_type: Option<UnitType>,
+}
+
+impl BattleUnitInfo {
+    /// Effective attack power of a single unit. Stats of units with base stats are derived by
+    /// `normalize_participants`.
+    fn attack_power(&self) -> f32 {
+        self.attack_power.unwrap_or_default()
+    }
+
+    /// Effective shield points of a single unit.
+    fn shield_points(&self) -> f32 {
+        self.shield_points.unwrap_or_default()
+    }
+
+    /// Effective hull plating of a single unit.
+    fn hull_plating(&self) -> f32 {
+        self.hull_plating.unwrap_or_default()
+    }
+
+    /// Derive the effective stats from the base stats with the technologies of the participant
+    /// including the bonuses of its class and officers.
+    ///
+    /// Effective stats which are provided directly are taken to be derived from the technologies without
+    /// these bonuses, so they are converted back to base stats to add the bonuses. They are left as is
+    /// when the participant has no bonuses.
+    fn derive_stats(&mut self, technologies: &Technologies, technologies_with_bonuses: &Technologies) {
+        let base_stats = match &self.base_stats {
+            Some(base_stats) => base_stats.clone(),
+            None if technologies != technologies_with_bonuses => technologies.base_stats(&UnitStats {
+                attack_power: self.attack_power(),
+                shield_points: self.shield_points(),
+                hull_plating: self.hull_plating(),
+            }),
+            None => return,
+        };
+
+        let stats = technologies_with_bonuses.derive_stats(&base_stats);
+        self.attack_power = Some(stats.attack_power);
+        self.shield_points = Some(stats.shield_points);
+        self.hull_plating = Some(stats.hull_plating);
+    }
+
+    /// Get the unit type, falling back to the defense unit id range of the combat rules.
+    fn resolve_unit_type(&self, rules: &BattleRules) -> UnitType {
+        match self.unit_type {
+            Some(unit_type) => unit_type,
+            None if rules.is_defense(self.unit_id) => UnitType::Defense,
+            None => UnitType::Ship,
+        }
+    }
+}
+
+/// Type of a unit, which determines e.g. the debris field percentage.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+enum UnitType {
+    Ship,
+    Defense,
+}
+
+/// Amount of resources, used for unit costs and the debris field.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq, Hash)]
+struct Resources {
+    metal: u64,
+    crystal: u64,
+    deuterium: u64,
+}
+
+impl Resources {
+    /// Add the cost of an amount of units to these resources, capped at `u64::MAX`.
+    fn add_cost(&mut self, cost: &Resources, amount: u64) {
+        self.metal = self.metal.saturating_add(cost.metal.saturating_mul(amount));
+        self.crystal = self.crystal.saturating_add(cost.crystal.saturating_mul(amount));
+        self.deuterium = self.deuterium.saturating_add(cost.deuterium.saturating_mul(amount));
+    }
+
+    /// Subtract other resources from these resources, never going below zero.
+    fn saturating_sub(&self, other: &Resources) -> Resources {
+        Resources {
+            metal: self.metal.saturating_sub(other.metal),
+            crystal: self.crystal.saturating_sub(other.crystal),
+            deuterium: self.deuterium.saturating_sub(other.deuterium),
+        }
+    }
 }
 
 /// Battle unit count to keep track of the amount of units of a certain type.
@@ -42,14 +300,6 @@ struct BattleUnitCount {
     amount: u32,
 }
 
-/// Battle unit instance which is used to keep track of indidivual units and their current health during battle.
-#[derive(Serialize, Deserialize, Clone)]
-struct BattleUnitInstance {
-    unit_id: i16,
-    current_shield_points: f32,
-    current_hull_plating: f32,
-}
-
 /// Battle round which is used to keep track of the battle statistics for a single round.
 #[derive(Serialize, Deserialize)]
 struct BattleRound {
@@ -77,14 +327,63 @@ struct BattleRound {
     hits_attacker: u32,
     /// Total amount of hits the defender made this round.
     hits_defender: u32,
+    /// Units and losses per attacking participant.
+    attacker_participants: Vec<ParticipantRound>,
+    /// Units and losses per defending participant.
+    defender_participants: Vec<ParticipantRound>,
+    /// Debris created by the losses of this round. Only set when debris settings are provided.
+    debris: Option<Resources>,
+}
+
+/// Units and losses of a single participant in a battle round.
+///
+/// This lets a client send every participant of an ACS battle its own report and return the
+/// surviving units to the right owner.
+#[derive(Serialize, Deserialize)]
+struct ParticipantRound {
+    participant_id: u64,
+    /// The units of the participant remaining at the end of the round.
+    ships: HashMap<i16, BattleUnitCount>,
+    /// Unit losses of the participant until now which includes previous rounds.
+    losses: HashMap<i16, BattleUnitCount>,
+    /// Unit losses of the participant in this round.
+    losses_in_round: HashMap<i16, BattleUnitCount>,
+}
+
+/// Outcome of the battle.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum BattleOutcome {
+    /// All defending units are destroyed while the attacker has units left, or there was nothing
+    /// to fight against.
+    AttackerWins,
+    /// All attacking units are destroyed. This includes the case where both sides are destroyed.
+    DefenderWins,
+    /// Both sides still have units left after the last round.
+    Draw,
+}
+
+/// Final result of one side of the battle.
+#[derive(Serialize, Deserialize)]
+struct SideResult {
+    /// The units of the side that survived the battle.
+    surviving_units: HashMap<i16, BattleUnitCount>,
+    /// The units of the side that were destroyed in the battle. Defense repairs are not
+    /// subtracted, see `repaired_defenses` for those.
+    lost_units: HashMap<i16, BattleUnitCount>,
+    /// Resource value of the lost units. Only set when the cost of all units of the side is provided.
+    lost_resources: Option<Resources>,
 }
 
 /// Memory metrics which is used to keep track of the peak memory usage during the battle.
 ///
-/// This is only used for debugging purposes and not actually consumed by the PHP client.
+/// This is only used for debugging purposes and not actually consumed by the PHP client. The peak
+/// memory is the RSS of the whole process, which includes the memory of the PHP client. Request
+/// `metrics` in the battle input for the memory allocated by the engine only.
 #[derive(Serialize, Deserialize)]
 struct MemoryMetrics {
     peak_memory: u64, // in kilobytes
+    estimated_memory: u64, // in kilobytes
 }
 
 /// Battle output which is returned to the PHP client.
@@ -93,123 +392,637 @@ struct MemoryMetrics {
 /// for debugging purposes when called from battle_engine_debug Rust project.
 #[derive(Serialize, Deserialize)]
 pub struct BattleOutput {
+    /// The seed that was used for the random number generator. Can be stored with the battle
+    /// report to replay the battle later.
+    seed: u64,
+    /// Amount of threads that were used to process the combat phases. Needed together with the seed
+    /// to replay the battle.
+    threads: usize,
+    /// Unit strategy that was used. Differs from the requested strategy when the battle didn't fit in
+    /// the memory budget. Needed together with the seed to replay the battle.
+    unit_strategy: UnitStrategy,
+    /// Rule set that was used. Needed together with the seed to replay the battle.
+    #[serde(default)]
+    rule_set: RuleSet,
+    /// Arithmetic that was used. Needed together with the seed to replay the battle.
+    #[serde(default)]
+    arithmetic: Arithmetic,
+    /// Whether the battle was stopped early by the caller of `fight_battle_rounds_streaming`.
+    #[serde(default)]
+    stopped_early: bool,
+    /// Outcome of the battle, based on the units that are left after the last round.
+    outcome: BattleOutcome,
+    /// Amount of rounds that were fought. This is lower than the maximum amount of rounds when one of
+    /// the sides was destroyed early.
+    rounds_fought: u32,
+    /// Final result of the attacking side.
+    attacker: SideResult,
+    /// Final result of the defending side.
+    defender: SideResult,
+    /// Ships of the defender that fled before the battle. Empty when no flee settings are provided or
+    /// the defender didn't flee.
+    #[serde(default)]
+    fled_units: FledUnits,
     rounds: Vec<BattleRound>,
+    /// Destroyed defense units of the defender that are repaired after the battle.
+    repaired_defenses: HashMap<i16, BattleUnitCount>,
+    /// Debris field created by the battle. Only set when debris settings are provided.
+    debris: Option<Resources>,
+    /// Moon chance in percent. Only set when moon settings are provided.
+    moon_chance: Option<u32>,
+    /// Whether a moon is created. Only set when moon settings are provided.
+    moon_created: Option<bool>,
     memory_metrics: MemoryMetrics,
+    /// Allocation, timing and shot metrics of the engine. Only set when requested in the battle input.
+    /// This is the last field, so the serialization of all other fields is included in the metrics.
+    #[serde(skip_deserializing, serialize_with = "metrics::serialize_metrics", skip_serializing_if = "Option::is_none")]
+    metrics: Option<MetricsRecorder>,
 }
 
 /// FFI interface to process the battle rounds and return the battle output.
 ///
-/// This is the method which is called from the PHP client in RustBattleEngine.php.
+/// This is the method which is called from the PHP client in RustBattleEngine.php. The returned
+/// string is owned by the caller and must be released with `free_battle_output`. It contains either
+/// the battle output or an error envelope if the battle could not be processed.
+///
+/// # Safety
+///
+/// `input_json` must be null or a valid pointer to a null-terminated C string.
+#[no_mangle]
+pub unsafe extern "C" fn fight_battle_rounds(input_json: *const c_char) -> *mut c_char {
+    ffi_output(|| fight_battle_rounds_json(input_json))
+}
+
+/// FFI interface to estimate the battle rounds and return the estimated battle output.
+///
+/// Instead of simulating every shot this models every round analytically with expected values, which
+/// takes microseconds regardless of the fleet size. The rounds have the same structure as the rounds of
+/// `fight_battle_rounds`, the output is marked with `"estimated": true`. See `estimate` for the model.
+///
+/// The returned string is owned by the caller and must be freed with `free_battle_output`.
+///
+/// # Safety
+///
+/// `input_json` must be null or a valid pointer to a null-terminated C string.
+#[no_mangle]
+pub unsafe extern "C" fn estimate_battle_rounds(input_json: *const c_char) -> *mut c_char {
+    ffi_output(|| estimate_battle_rounds_json(input_json))
+}
+
+/// FFI interface to process a batch of battles in a single call.
+///
+/// The input is a JSON object with a `battles` list, where every battle has an `id` and an `input` with
+/// the same battle input as `fight_battle_rounds`, and an optional amount of `threads` to process
+/// battles at the same time. The output contains a `results` list in the same order, with either the
+/// battle `output` or the `error` of every battle. The returned string is owned by the caller and must
+/// be freed with `free_battle_output`.
+///
+/// # Safety
+///
+/// `input_json` must be null or a valid pointer to a null-terminated C string.
+#[no_mangle]
+pub unsafe extern "C" fn fight_battles_batch(input_json: *const c_char) -> *mut c_char {
+    ffi_output(|| batch::fight_battles_batch(input_str(input_json)?))
+}
+
+/// Callback of `fight_battle_rounds_streaming` which is called with every round as soon as it is
+/// finished.
+///
+/// `round_json` is the round as JSON, with the same structure as the rounds of the battle output, and
+/// `round_number` starts at 1. The string is only valid during the call and must not be freed by the
+/// callback. `user_data` is passed through unchanged. Return true to continue the battle, or false to
+/// stop it after this round.
+pub type RoundCallback = extern "C" fn(round_json: *const c_char, round_number: u32, user_data: *mut c_void) -> bool;
+
+/// FFI interface to process the battle rounds and pass every round to a callback as soon as it is
+/// finished, e.g. to show a battle in progress or push the rounds over a websocket.
+///
+/// The callback can stop the battle early, in which case the battle output contains the rounds until
+/// then with `"stopped_early": true`, and no defense repairs or moon creation are rolled. When no
+/// callback is passed this is the same as `fight_battle_rounds`. The returned string contains the full
+/// battle output or an error envelope, is owned by the caller and must be freed with
+/// `free_battle_output`.
+///
+/// # Safety
+///
+/// `input_json` must be null or a valid pointer to a null-terminated C string. `on_round` must be safe
+/// to call with `user_data` from the calling thread, and must not unwind.
+#[no_mangle]
+pub unsafe extern "C" fn fight_battle_rounds_streaming(
+    input_json: *const c_char,
+    on_round: Option<RoundCallback>,
+    user_data: *mut c_void,
+) -> *mut c_char {
+    ffi_output(|| {
+        let battle_input = parse_battle_input(input_str(input_json)?.as_bytes(), WireFormat::Json)?;
+
+        let mut round_error = None;
+        let mut round_number = 0;
+        let battle_output = process_battle_rounds_streaming(battle_input, &mut |round| {
+            let Some(on_round) = on_round else {
+                return true;
+            };
+            round_number += 1;
+
+            // serde_json escapes null bytes, so the round never contains an interior null byte.
+            match serde_json::to_string(round) {
+                Ok(round_json) => {
+                    let round_json = CString::new(round_json).unwrap_or_default();
+                    on_round(round_json.as_ptr(), round_number, user_data)
+                }
+                Err(error) => {
+                    round_error = Some(BattleError::new(BattleErrorCode::Serialization, error.to_string()));
+                    false
+                }
+            }
+        })?;
+        if let Some(error) = round_error {
+            return Err(error);
+        }
+
+        serde_json::to_string(&battle_output)
+            .map_err(|error| BattleError::new(BattleErrorCode::Serialization, error.to_string()))
+    })
+}
+
+/// FFI interface to process the battle rounds with the input and output encoded in the given format.
+///
+/// `format` is 0 for JSON and 1 for MessagePack, see `WireFormat`. The input is passed as a buffer with
+/// its length, as MessagePack can contain null bytes. The output, or the error envelope if the battle
+/// could not be processed, is encoded in the same format, except for an unknown format which results in
+/// a JSON error envelope.
+///
+/// The length of the returned buffer is written to `output_len`. The buffer is owned by the caller and
+/// must be released with `free_battle_output_buffer`. Returns null if `output_len` is null.
+///
+/// # Safety
+///
+/// `input` must be null or a valid pointer to `input_len` bytes. `output_len` must be null or a valid
+/// pointer to write the length of the output to.
 #[no_mangle]
-pub extern "C" fn fight_battle_rounds(input_json: *const c_char) -> *mut c_char {
-    let input_str = unsafe { CStr::from_ptr(input_json).to_str().unwrap() };
-    let battle_input: BattleInput = serde_json::from_str(input_str).unwrap();
-    let battle_output = process_battle_rounds(battle_input);
-    let result_json = serde_json::to_string(&battle_output).unwrap();
-    let c_str = CString::new(result_json).unwrap();
-    c_str.into_raw()
+pub unsafe extern "C" fn fight_battle_rounds_encoded(
+    input: *const u8,
+    input_len: usize,
+    format: u32,
+    output_len: *mut usize,
+) -> *mut u8 {
+    if output_len.is_null() {
+        return std::ptr::null_mut();
+    }
+
+    let wire_format = WireFormat::from_flag(format);
+    let output = catch_output(wire_format.unwrap_or(WireFormat::Json), || {
+        let Some(wire_format) = wire_format else {
+            return Err(BattleError::from_issues(vec![ValidationIssue::new(
+                "format",
+                format!("unknown format {}, must be 0 (JSON) or 1 (MessagePack)", format),
+            )]));
+        };
+        if input.is_null() {
+            return Err(BattleError::new(BattleErrorCode::NullPointer, "input is a null pointer"));
+        }
+
+        fight_battle(std::slice::from_raw_parts(input, input_len), wire_format)
+    });
+
+    *output_len = output.len();
+    Box::into_raw(output.into_boxed_slice()) as *mut u8
+}
+
+/// FFI interface to free a buffer that was returned by `fight_battle_rounds_encoded`. Passing a null
+/// pointer is a no-op.
+///
+/// # Safety
+///
+/// `output` must be a pointer returned by `fight_battle_rounds_encoded` that has not been freed yet, and
+/// `output_len` the length that was returned with it. The pointer must not be used after this call.
+#[no_mangle]
+pub unsafe extern "C" fn free_battle_output_buffer(output: *mut u8, output_len: usize) {
+    if output.is_null() {
+        return;
+    }
+
+    // Take back ownership of the buffer so it is deallocated by the Rust allocator.
+    drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(output, output_len)));
+}
+
+/// FFI interface to process the battle rounds with C structs instead of JSON.
+///
+/// The units are passed as arrays of `NativeUnit` records with a shared rapidfire table, and the
+/// remaining units of every round are returned as flat arrays in the order of the input units. See
+/// `native` for the supported features. An error is returned as JSON envelope in the `error` field of
+/// the output.
+///
+/// The returned output is never null, is owned by the caller and must be released with
+/// `free_battle_output_native`.
+///
+/// # Safety
+///
+/// `input` must be null or a valid pointer to a battle input whose arrays are valid for the given counts.
+#[no_mangle]
+pub unsafe extern "C" fn fight_battle_rounds_native(input: *const NativeBattleInput) -> *mut NativeBattleOutput {
+    // Catch all panics here as unwinding across the FFI boundary would kill the PHP worker.
+    let output = match panic::catch_unwind(AssertUnwindSafe(|| native::fight_battle_native(input))) {
+        Ok(Ok(output)) => output,
+        Ok(Err(error)) => NativeBattleOutput::from_error(error),
+        Err(payload) => NativeBattleOutput::from_error(BattleError::from_panic(payload)),
+    };
+
+    Box::into_raw(Box::new(output))
+}
+
+/// FFI interface to free an output that was returned by `fight_battle_rounds_native`, including all of
+/// its arrays. Passing a null pointer is a no-op.
+///
+/// # Safety
+///
+/// `output` must be a pointer returned by `fight_battle_rounds_native` that has not been freed yet. The
+/// pointer and its arrays must not be used after this call.
+#[no_mangle]
+pub unsafe extern "C" fn free_battle_output_native(output: *mut NativeBattleOutput) {
+    if output.is_null() {
+        return;
+    }
+
+    Box::from_raw(output).free();
+}
+
+/// FFI interface to free a string that was returned by `fight_battle_rounds`, `estimate_battle_rounds`,
+/// `fight_battles_batch` or `fight_battle_rounds_streaming`.
+///
+/// The PHP client calls this after it has copied the battle output into a PHP string. Passing a
+/// null pointer is a no-op.
+///
+/// # Safety
+///
+/// `output` must be a pointer returned by `fight_battle_rounds`, `estimate_battle_rounds`,
+/// `fight_battles_batch` or `fight_battle_rounds_streaming` that has not been freed yet. The pointer must
+/// not be used after this call.
+#[no_mangle]
+pub unsafe extern "C" fn free_battle_output(output: *mut c_char) {
+    if output.is_null() {
+        return;
+    }
+
+    // Take back ownership of the string so it is deallocated by the Rust allocator.
+    drop(CString::from_raw(output));
+}
+
+/// Run the processing of an FFI call and convert its result into a string owned by the caller.
+///
+/// Errors and panics are converted into the JSON error envelope.
+fn ffi_output(process: impl FnOnce() -> Result<String, BattleError>) -> *mut c_char {
+    let output_json = catch_output(WireFormat::Json, || process().map(String::into_bytes));
+
+    // serde_json escapes null bytes, so the output never contains an interior null byte.
+    CString::new(output_json).unwrap_or_default().into_raw()
+}
+
+/// Run the processing of an FFI call and return its output, or the error envelope in the given format
+/// if it failed or panicked.
+fn catch_output(format: WireFormat, process: impl FnOnce() -> Result<Vec<u8>, BattleError>) -> Vec<u8> {
+    // Catch all panics here as unwinding across the FFI boundary would kill the PHP worker.
+    match panic::catch_unwind(AssertUnwindSafe(process)) {
+        Ok(Ok(output)) => output,
+        Ok(Err(error)) => error.encode(format),
+        Err(payload) => BattleError::from_panic(payload).encode(format),
+    }
+}
+
+/// Parse the JSON input, process the battle rounds and return the battle output as JSON.
+///
+/// # Safety
+///
+/// `input_json` must be null or a valid pointer to a null-terminated C string.
+unsafe fn fight_battle_rounds_json(input_json: *const c_char) -> Result<String, BattleError> {
+    let output = fight_battle(input_str(input_json)?.as_bytes(), WireFormat::Json)?;
+
+    String::from_utf8(output).map_err(|error| BattleError::new(BattleErrorCode::Serialization, error.to_string()))
+}
+
+/// Parse the JSON input, estimate the battle rounds and return the estimated battle output as JSON.
+///
+/// # Safety
+///
+/// `input_json` must be null or a valid pointer to a null-terminated C string.
+unsafe fn estimate_battle_rounds_json(input_json: *const c_char) -> Result<String, BattleError> {
+    let battle_input = parse_battle_input(input_str(input_json)?.as_bytes(), WireFormat::Json)?;
+    let battle_estimate = estimate::estimate_battle_rounds(battle_input);
+
+    serde_json::to_string(&battle_estimate)
+        .map_err(|error| BattleError::new(BattleErrorCode::Serialization, error.to_string()))
+}
+
+/// Parse the battle input, process the battle rounds and return the battle output, both in the given
+/// format.
+fn fight_battle(input: &[u8], format: WireFormat) -> Result<Vec<u8>, BattleError> {
+    let battle_input = parse_battle_input(input, format)?;
+    let battle_output = process_battle_rounds(battle_input)?;
+
+    format.serialize(&battle_output)
+}
+
+/// Get the JSON input string from the pointer passed to the FFI interface.
+///
+/// # Safety
+///
+/// `input_json` must be null or a valid pointer to a null-terminated C string which outlives the
+/// returned string.
+unsafe fn input_str<'a>(input_json: *const c_char) -> Result<&'a str, BattleError> {
+    if input_json.is_null() {
+        return Err(BattleError::new(BattleErrorCode::NullPointer, "input_json is a null pointer"));
+    }
+
+    CStr::from_ptr(input_json)
+        .to_str()
+        .map_err(|error| BattleError::new(BattleErrorCode::InvalidUtf8, error.to_string()))
+}
+
+/// Parse and validate the battle input in the given format.
+fn parse_battle_input(input: &[u8], format: WireFormat) -> Result<BattleInput, BattleError> {
+    // Deserialize through serde_path_to_error so errors point to the offending field. Unknown
+    // fields are collected so they can be rejected in strict mode.
+    let mut unknown_fields = Vec::new();
+    let mut collect_unknown_field = |path: serde_ignored::Path| unknown_fields.push(path.to_string());
+    let battle_input: BattleInput = match format {
+        WireFormat::Json => {
+            let input_str = std::str::from_utf8(input)
+                .map_err(|error| BattleError::new(BattleErrorCode::InvalidUtf8, error.to_string()))?;
+            let deserializer = &mut serde_json::Deserializer::from_str(input_str);
+            let deserializer = serde_ignored::Deserializer::new(deserializer, &mut collect_unknown_field);
+            serde_path_to_error::deserialize(deserializer)?
+        }
+        WireFormat::MessagePack => {
+            let deserializer = &mut rmp_serde::Deserializer::new(input);
+            let deserializer = serde_ignored::Deserializer::new(deserializer, &mut collect_unknown_field);
+            serde_path_to_error::deserialize(deserializer)
+                .map_err(|error| BattleError::from_path_error(error, format.decode_error_code()))?
+        }
+    };
+
+    // Validate the input before processing, so invalid values result in a precise error
+    // instead of a panic or nonsense battle rounds.
+    validation::validate_battle_input(&battle_input, &unknown_fields)?;
+
+    Ok(battle_input)
 }
 
 /// Process the battle rounds and return the battle output.
-fn process_battle_rounds(input: BattleInput) -> BattleOutput {
+fn process_battle_rounds(input: BattleInput) -> Result<BattleOutput, BattleError> {
+    process_battle_rounds_streaming(input, &mut |_| true)
+}
+
+/// Process the battle rounds and pass every round to `on_round` as soon as it is finished.
+///
+/// When `on_round` returns false the battle is stopped after that round. The output then contains the
+/// rounds until then, and no defense repairs or moon creation are rolled as the battle didn't end.
+fn process_battle_rounds_streaming(
+    input: BattleInput,
+    on_round: &mut dyn FnMut(&BattleRound) -> bool,
+) -> Result<BattleOutput, BattleError> {
+    match input.arithmetic {
+        Arithmetic::Float => fight_battle_rounds_with::<f32>(input, on_round),
+        Arithmetic::Integer => fight_battle_rounds_with::<u32>(input, on_round),
+    }
+}
+
+/// Process the battle rounds with the shield points, hull plating and damage stored as `P`.
+fn fight_battle_rounds_with<P: Points>(
+    mut input: BattleInput,
+    on_round: &mut dyn FnMut(&BattleRound) -> bool,
+) -> Result<BattleOutput, BattleError> {
+    let mut metrics = MetricsRecorder::start(input.metrics);
     let mut peak_memory = 0;
     let mut rounds = Vec::new();
+    let mut stopped_early = false;
+    let mut debris = input.debris.as_ref().map(|_| Resources::default());
 
-    // Create individual ships from provided battle unit info which contains the amount
-    let mut attacker_units = expand_units(&input.attacker_units);
-    let mut defender_units = expand_units(&input.defender_units);
+    // Treat single fleets as a single participant so the rest of the engine only deals with participants.
+    normalize_participants(&mut input);
+
+    // Ships of the defender flee before any units are created when the attacker is much stronger.
+    let fled_units = flee::flee_before_combat(&mut input);
+
+    // All dice rolls are done with a single seeded RNG so the battle can be reproduced.
+    let seed = input.seed.unwrap_or_else(generate_seed);
+    let mut rng = BattleRng::seed_from_u64(seed);
+
+    // Large battles can optionally be processed by multiple threads.
+    let threads = parallel::resolve_threads(input.threads);
+
+    // Unit types are interned into dense indices so the combat loop can use flat lookup tables.
+    let unit_types = UnitTypeIndex::new(&input.attackers, &input.defenders);
+
+    // Check the memory budget before any units are created, switching to aggregated units or
+    // rejecting the battle if it doesn't fit.
+    let (unit_strategy, estimated_memory) = budget::select_unit_strategy(
+        &input.attackers,
+        &input.defenders,
+        &unit_types,
+        input.unit_strategy,
+        input.rules.max_rounds,
+        threads,
+        budget::resolve_memory_budget(input.memory_budget),
+    )?;
+
+    // Create the units of both sides. Depending on the strategy every unit is stored individually or
+    // undamaged units are grouped per type.
+    let mut attacker_units = UnitStore::<P>::new(&input.attackers, &unit_types, unit_strategy, input.rule_set);
+    let mut defender_units = UnitStore::<P>::new(&input.defenders, &unit_types, unit_strategy, input.rule_set);
+    metrics.record_expand();
 
     // Track peak memory usage for debugging purposes
     update_peak_memory(&mut peak_memory);
 
-    // Fight up to 6 rounds
-    for _ in 0..6 {
+    // Fight up to the configured amount of rounds (6 by default)
+    let combat = CombatParams::new(&input.rules, input.rule_set);
+    for _ in 0..input.rules.max_rounds {
         if attacker_units.is_empty() || defender_units.is_empty() {
             break;
         }
 
-        let mut round = BattleRound {
-            attacker_ships: HashMap::new(),
-            defender_ships: HashMap::new(),
-            attacker_losses: HashMap::new(),
-            defender_losses: HashMap::new(),
-            attacker_losses_in_round: HashMap::new(),
-            defender_losses_in_round: HashMap::new(),
-            absorbed_damage_attacker: 0.0,
-            absorbed_damage_defender: 0.0,
-            full_strength_attacker: 0.0,
-            full_strength_defender: 0.0,
-            hits_attacker: 0,
-            hits_defender: 0,
-        };
+        let mut round = new_battle_round(&input);
 
         // Process combat
-        process_combat(&mut attacker_units, &mut defender_units, &mut round, &input.attacker_units, &input.defender_units, true);
-        process_combat(&mut defender_units, &mut attacker_units, &mut round, &input.defender_units, &input.attacker_units, false);
+        let (shots_attacker, shots_defender) = if threads > 1 {
+            (
+                parallel::process_combat_parallel(&attacker_units, &mut defender_units, &mut round, &combat, true, &mut rng, threads),
+                parallel::process_combat_parallel(&defender_units, &mut attacker_units, &mut round, &combat, false, &mut rng, threads),
+            )
+        } else {
+            (
+                process_combat(&attacker_units, &mut defender_units, &mut round, &combat, true, &mut rng),
+                process_combat(&defender_units, &mut attacker_units, &mut round, &combat, false, &mut rng),
+            )
+        };
 
         // Cleanup round
-        cleanup_round(&mut round, &mut attacker_units, &mut defender_units, &input.attacker_units, &input.defender_units);
+        cleanup_round(&mut round, &mut attacker_units, &mut defender_units, &input.rules);
 
         // Update round statistics
-        round.attacker_ships = compress_units(&attacker_units);
-        round.defender_ships = compress_units(&defender_units);
+        attacker_units.count_units(&mut round.attacker_participants);
+        defender_units.count_units(&mut round.defender_participants);
+        round.attacker_ships = merge_participant_counts(&round.attacker_participants, |participant| &participant.ships);
+        round.defender_ships = merge_participant_counts(&round.defender_participants, |participant| &participant.ships);
 
         // Calculate accumulated losses
-        calculate_losses(&mut round, &input.attacker_units, &input.defender_units);
+        calculate_losses(&mut round, &input.attackers, &input.defenders);
 
-        rounds.push(round);
+        // Calculate the debris created in this round
+        calculate_round_debris(&mut round, &input, &mut debris);
+
+        metrics.record_round(shots_attacker, shots_defender);
 
          // Track peak memory usage for debugging purposes
         update_peak_memory(&mut peak_memory);
+
+        // Deliver the round to the caller, which can stop the battle early.
+        let continue_battle = on_round(&round);
+        rounds.push(round);
+        if !continue_battle {
+            stopped_early = true;
+            break;
+        }
     }
 
-    BattleOutput {
+    // Roll for repair of the destroyed defenses after the last round.
+    let repaired_defenses = match rounds.last() {
+        Some(last_round) if !stopped_early => {
+            repair_defenses(&input.defenders, &last_round.defender_participants, &input.rules, &mut rng)
+        }
+        _ => HashMap::new(),
+    };
+
+    // Roll for the moon creation after the defense repairs, so the moon outcome is reproduced by
+    // the same seed as well.
+    let mut moon_chance = None;
+    let mut moon_created = None;
+    if let (Some(settings), Some(debris), false) = (&input.moon, &debris, stopped_early) {
+        let chance = moon::calculate_moon_chance(debris, settings);
+        moon_chance = Some(chance);
+        moon_created = Some(moon::roll_moon_creation(chance, settings, &mut rng));
+    }
+
+    // Summarize the battle for each side.
+    let (attacker_rounds, defender_rounds) = match rounds.last() {
+        Some(last_round) => (&last_round.attacker_participants[..], &last_round.defender_participants[..]),
+        None => (&[][..], &[][..]),
+    };
+    let attacker = new_side_result(attacker_units.count_unit_types(), &input.attackers, attacker_rounds);
+    let defender = new_side_result(defender_units.count_unit_types(), &input.defenders, defender_rounds);
+    let outcome = determine_outcome(rounds.len(), !attacker_units.is_empty(), !defender_units.is_empty());
+    metrics.record_compress();
+
+    Ok(BattleOutput {
+        seed,
+        threads,
+        unit_strategy,
+        rule_set: input.rule_set,
+        arithmetic: input.arithmetic,
+        stopped_early,
+        outcome,
+        rounds_fought: rounds.len() as u32,
+        attacker,
+        defender,
+        fled_units,
         rounds,
+        repaired_defenses,
+        debris,
+        moon_chance,
+        moon_created,
         memory_metrics: MemoryMetrics {
             peak_memory,
+            estimated_memory,
         },
-    }
+        metrics: metrics.is_enabled().then_some(metrics),
+    })
 }
 
-/// Expands unit information into individual unit objects, allowing the engine to track the state
-/// of each unit (e.g., shields and hull points) independently during combat.
-fn expand_units(units: &HashMap<i16, BattleUnitInfo>) -> Vec<BattleUnitInstance> {
-    let mut expanded = Vec::new();
-    for (_, unit) in units {
-        for _ in 0..unit.amount {
-            expanded.push(BattleUnitInstance {
-                unit_id: unit.unit_id.clone(),
-                current_shield_points: unit.shield_points,
-                current_hull_plating: unit.hull_plating
-            });
+/// Move the single fleet per side (`attacker_units` and `defender_units`) into the participant lists
+/// as a participant with id 0, and apply the stat bonuses and extra rapidfire of the class and officers
+/// of every participant. Units with base stats get their effective stats derived here.
+fn normalize_participants(input: &mut BattleInput) {
+    if !input.attacker_units.is_empty() {
+        input.attackers = vec![BattleParticipant {
+            participant_id: 0,
+            units: std::mem::take(&mut input.attacker_units),
+            technologies: std::mem::take(&mut input.attacker_technologies),
+        }];
+    }
+
+    if !input.defender_units.is_empty() {
+        input.defenders = vec![BattleParticipant {
+            participant_id: 0,
+            units: std::mem::take(&mut input.defender_units),
+            technologies: std::mem::take(&mut input.defender_technologies),
+        }];
+    }
+
+    for participant in input.attackers.iter_mut().chain(input.defenders.iter_mut()) {
+        let technologies_with_bonuses = input.bonus_table.apply(&participant.technologies);
+        for unit in participant.units.values_mut() {
+            unit.derive_stats(&participant.technologies, &technologies_with_bonuses);
+            input.bonus_table.apply_rapidfire(&participant.technologies, unit.unit_id, &mut unit.rapidfire);
         }
     }
+}
 
-    expanded
+/// Generate a random seed for battles where no seed was provided.
+///
+/// The seed is limited to the positive i64 range so it fits in a PHP integer.
+fn generate_seed() -> u64 {
+    rand::thread_rng().gen_range(0..=i64::MAX as u64)
 }
 
-/// Compress individual unit instances into a single unit metadata object which stores the amount of units
-/// instead of having a separate object for each unit. This is for only passing data about total amount
-/// of units per type.
-fn compress_units(units: &Vec<BattleUnitInstance>) -> HashMap<i16, BattleUnitCount> {
-    units.iter()
-        // Loop over all units and count the amount of units per unit_id.
-        .fold(HashMap::new(), |mut counts, unit| {
-            // Increment count for each unit_id
-            *counts.entry(unit.unit_id).or_insert(0) += 1;
-            counts
-        })
-        .into_iter()
-        // Convert counts hashmap to expected BattleUnitCount hashmap
-        .map(|(unit_id, count)| {
-            (unit_id, BattleUnitCount {
-                unit_id,
-                amount: count,
-            })
+/// Create an empty battle round for the participants of the battle.
+fn new_battle_round(input: &BattleInput) -> BattleRound {
+    BattleRound {
+        attacker_ships: HashMap::new(),
+        defender_ships: HashMap::new(),
+        attacker_losses: HashMap::new(),
+        defender_losses: HashMap::new(),
+        attacker_losses_in_round: HashMap::new(),
+        defender_losses_in_round: HashMap::new(),
+        absorbed_damage_attacker: 0.0,
+        absorbed_damage_defender: 0.0,
+        full_strength_attacker: 0.0,
+        full_strength_defender: 0.0,
+        hits_attacker: 0,
+        hits_defender: 0,
+        attacker_participants: new_participant_rounds(&input.attackers),
+        defender_participants: new_participant_rounds(&input.defenders),
+        debris: None,
+    }
+}
+
+/// Create the empty round statistics for every participant of a side.
+fn new_participant_rounds(participants: &[BattleParticipant]) -> Vec<ParticipantRound> {
+    participants
+        .iter()
+        .map(|participant| ParticipantRound {
+            participant_id: participant.participant_id,
+            ships: HashMap::new(),
+            losses: HashMap::new(),
+            losses_in_round: HashMap::new(),
         })
         .collect()
 }
 
+/// Merge the unit counts of all participants of a side into a single count per unit type.
+fn merge_participant_counts(
+    participant_rounds: &[ParticipantRound],
+    counts: impl Fn(&ParticipantRound) -> &HashMap<i16, BattleUnitCount>,
+) -> HashMap<i16, BattleUnitCount> {
+    let mut merged = HashMap::new();
+    for participant_round in participant_rounds {
+        for unit in counts(participant_round).values() {
+            increment_battle_unit_count_amount(&mut merged, unit.unit_id, unit.amount);
+        }
+    }
+
+    merged
+}
+
 /// Simulates combat for a single round between two groups of units.
 ///
 /// # Why:
@@ -221,100 +1034,169 @@ fn compress_units(units: &Vec<BattleUnitInstance>) -> HashMap<i16, BattleUnitCou
 /// - `attackers`: Units attacking in this phase.
 /// - `defenders`: Units being attacked in this phase.
 /// - `round`: Stores round statistics, such as hits and absorbed damage.
-/// - `attacker_unit_metadata`: Metadata for attacker units to determine damage, rapidfire, etc.
-/// - `defender_unit_metadata`: Metadata for defender units to determine max shield points etc.
+/// - `combat`: Combat parameters such as the shield bounce and explosion factors and the rule set.
 /// - `is_attacker`: Whether the current phase is attacker-to-defender or vice versa.
-fn process_combat(
-    attackers: &mut Vec<BattleUnitInstance>,
-    defenders: &mut Vec<BattleUnitInstance>,
+/// - `rng`: Seeded random number generator of the battle.
+///
+/// # Returns:
+/// The amount of fired shots, including bounced shots.
+fn process_combat<P: Points>(
+    attackers: &UnitStore<P>,
+    defenders: &mut UnitStore<P>,
     round: &mut BattleRound,
-    attacker_unit_metadata: &HashMap<i16, BattleUnitInfo>,
-    defender_unit_metadata: &HashMap<i16, BattleUnitInfo>,
+    combat: &CombatParams,
     is_attacker: bool,
-) {
-    let mut rng = rand::thread_rng();
+    rng: &mut BattleRng,
+) -> u64 {
+    // Nothing to shoot at. This also guards the target selection below against an empty range.
+    // Splitting units out of their group does not change the amount of targets during the round.
+    let target_count = defenders.len();
+    if target_count == 0 {
+        return 0;
+    }
 
-    for attacker in attackers.iter() {
-        let mut continue_attacking = true;
+    let mut fired = 0;
 
-        // Get metadata of the attacking unit.
-        let attacker_metadata = attacker_unit_metadata.get(&attacker.unit_id).unwrap();
-        let damage = attacker_metadata.attack_power;
+    for (attacker_group_idx, attacker_group, amount) in attackers.shooters() {
+        let damage = attacker_group.attack_power;
 
-        while continue_attacking {
-            continue_attacking = false;
+        for _ in 0..amount {
+            let mut continue_attacking = true;
 
-            // Select a random defender as a target
-            let target_idx = rng.gen_range(0..defenders.len());
-            let target = &mut defenders[target_idx];
+            while continue_attacking {
+                continue_attacking = false;
 
-            // Get metadata of the defending unit.
-            let target_metadata = defender_unit_metadata.get(&target.unit_id).unwrap();
+                // Select a random defender as a target
+                let target_idx = rng.gen_range(0..target_count);
+                let target = defenders.target_mut(target_idx);
+                let target_group = target.group;
+                fired += 1;
 
-            // Check if the damage is less than 1% of the target's shield points. If so,
-            // attack is negated.
-            if damage < (0.01 * target_metadata.shield_points) {
-                continue
-            }
+                let shot = apply_shot(
+                    damage,
+                    target.current_shield_points,
+                    target.current_hull_plating,
+                    target_group,
+                    combat,
+                    rng,
+                );
 
-            // Apply damage to shields first, then hull plating
-            let mut shield_absorption = 0.0;
-            if target.current_shield_points > 0.0 {
-                if damage <= target.current_shield_points {
-                    shield_absorption = damage;
-                    target.current_shield_points -= damage;
-                } else {
-                    shield_absorption = target.current_shield_points;
-                    target.current_hull_plating -= damage - target.current_shield_points;
-                    target.current_shield_points = 0.0;
+                match shot {
+                    // Update round statistics for hits and damage absorbed
+                    Some(shield_absorption) => record_hits(round, is_attacker, 1, damage.to_f64(), shield_absorption.to_f64()),
+                    // The shot bounced off, which ends the rapidfire chain with the legacy rule set.
+                    None if combat.rule_set.bounce_ends_rapidfire() => continue,
+                    None => {}
                 }
-            } else {
-                target.current_hull_plating -= damage;
-            }
 
-            // If hull integrity < 70%, then unit can explode randomly. Roll dice to see if it does.
-            if target.current_hull_plating / target_metadata.hull_plating < 0.7 {
-                let explosion_chance = 100.0 - ((target.current_hull_plating / target_metadata.hull_plating) * 100.0);
-                let roll = rng.gen_range(0..=100);
-                if roll < explosion_chance as i32 {
-                    // Unit explodes, set current hull plating and shield points to 0.
-                    target.current_hull_plating = 0.0;
-                    target.current_shield_points = 0.0;
+                // Check if the current unit has rapidfire against the target unit. If so, then
+                // roll dice to see if the current unit can attack again. The chances are calculated
+                // once when the units are created, see `RuleSet::rapidfire_chance`.
+                continue_attacking = if let Some(rapidfire_chance) = attackers.rapidfire_chance(attacker_group_idx, target_group.type_idx) {
+                    // Roll for rapidfire
+                    let roll = rng.gen_range(0.0..100.0);
+
+                    // If the roll is less than or equal to the rapidfire chance, the unit can attack again
+                    // and continue_attacking is set to true which will cause the loop to continue.
+                    roll <= rapidfire_chance
+                } else {
+                    false
                 }
             }
+        }
+    }
 
-            // Update round statistics for hits and damage absorbed
-            if is_attacker {
-                round.hits_attacker += 1;
-                round.full_strength_attacker += damage as f64;
-                round.absorbed_damage_defender += shield_absorption as f64;
-            } else {
-                round.hits_defender += 1;
-                round.full_strength_defender += damage as f64;
-                round.absorbed_damage_attacker += shield_absorption as f64;
-            }
+    fired
+}
 
-            // Check if the current unit has rapidfire against the target unit. If so, then
-            // roll dice to see if the current unit can attack again.
-            continue_attacking = if let Some(rapidfire_amount) = attacker_metadata.rapidfire.get(&target.unit_id) {
-                // Rapidfire chance is calculated as 100 - (100 / amount). For example:
-                // - rapidfire amount of 4 means 100 - (100 / 4) = 75% chance.
-                // - rapidfire amount of 10 means 100 - (100 / 10) = 90% chance.
-                // - rapidfire amount of 33 means 100 - (100 / 33) = 96.97%
-                let chance = 100.0 / *rapidfire_amount as f64;
-                let rounded_chance = (chance * 100.0).floor() / 100.0;
-                let rapidfire_chance = 100.0 - rounded_chance;
-
-                // Roll for rapidfire
-                let roll = rng.gen_range(0.0..100.0);
-
-                // If the roll is less than or equal to the rapidfire chance, the unit can attack again
-                // and continue_attacking is set to true which will cause the loop to continue.
-                roll <= rapidfire_chance
-            } else {
-                false
-            }
+/// Combat parameters of a battle, taken once from the combat rules and the rule set.
+#[derive(Clone, Copy)]
+struct CombatParams {
+    /// Shots with damage below this percentage of the target's shield points bounce off.
+    shield_bounce_percentage: f32,
+    /// Units with hull plating below this percentage of their original hull plating can explode.
+    explosion_hull_percentage: f32,
+    rule_set: RuleSet,
+}
+
+impl CombatParams {
+    fn new(rules: &BattleRules, rule_set: RuleSet) -> Self {
+        CombatParams {
+            shield_bounce_percentage: rules.shield_bounce_percentage,
+            explosion_hull_percentage: rules.explosion_hull_percentage,
+            rule_set,
+        }
+    }
+
+    /// Whether a shot with the given damage bounces off the target without doing any damage.
+    fn bounces<P: Points>(&self, damage: P, current_shield_points: P, group: &UnitGroup<P>) -> bool {
+        let shield_points = if self.rule_set.bounces_off_current_shields() {
+            current_shield_points
+        } else {
+            group.shield_points
+        };
+
+        damage.is_below_percentage(shield_points, self.shield_bounce_percentage)
+    }
+}
+
+/// Apply the damage of a single shot to the target and roll whether the target explodes.
+///
+/// Returns the amount of damage that was absorbed by the shield of the target, or `None` when the shot
+/// bounced off.
+fn apply_shot<P: Points>(
+    damage: P,
+    current_shield_points: &mut P,
+    current_hull_plating: &mut P,
+    group: &UnitGroup<P>,
+    combat: &CombatParams,
+    rng: &mut BattleRng,
+) -> Option<P> {
+    // Check if the damage is less than 1% (by default) of the target's shield points. If so, attack is
+    // negated.
+    if combat.bounces(damage, *current_shield_points, group) {
+        return None;
+    }
+
+    // Apply damage to shields first, then hull plating
+    let hull_damaged = damage > *current_shield_points;
+    let mut shield_absorption = P::ZERO;
+    if *current_shield_points > P::ZERO {
+        if damage <= *current_shield_points {
+            shield_absorption = damage;
+            *current_shield_points = current_shield_points.take_damage(damage);
+        } else {
+            shield_absorption = *current_shield_points;
+            *current_hull_plating = current_hull_plating.take_damage(damage.take_damage(*current_shield_points));
+            *current_shield_points = P::ZERO;
         }
+    } else {
+        *current_hull_plating = current_hull_plating.take_damage(damage);
+    }
+
+    // If hull integrity < 70% (by default), then unit can explode randomly. Roll dice to see if it does.
+    // The official rule set only rolls when the shot damaged the hull.
+    let can_explode = hull_damaged || combat.rule_set.explodes_without_hull_damage();
+    if can_explode && P::explodes(*current_hull_plating, group.hull_plating, combat.explosion_hull_percentage, rng) {
+        // Unit explodes, set current hull plating and shield points to 0.
+        *current_hull_plating = P::ZERO;
+        *current_shield_points = P::ZERO;
+    }
+
+    Some(shield_absorption)
+}
+
+/// Add hits, their total damage and the damage absorbed by the shields of the other side to the
+/// round statistics of the shooting side.
+fn record_hits(round: &mut BattleRound, is_attacker: bool, hits: u32, full_strength: f64, absorbed_damage: f64) {
+    if is_attacker {
+        round.hits_attacker += hits;
+        round.full_strength_attacker += full_strength;
+        round.absorbed_damage_defender += absorbed_damage;
+    } else {
+        round.hits_defender += hits;
+        round.full_strength_defender += full_strength;
+        round.absorbed_damage_attacker += absorbed_damage;
     }
 }
 
@@ -325,86 +1207,186 @@ fn process_combat(
 /// - Rolling dice for hull integrity < 70% of original if the unit is also destroyed.
 /// - Applying shield regeneration.
 /// - Calculate the total damage dealt by the attacker and defender and calculate shield absorption stats.
-fn cleanup_round(
+fn cleanup_round<P: Points>(
     round: &mut BattleRound,
-    attackers: &mut Vec<BattleUnitInstance>,
-    defenders: &mut Vec<BattleUnitInstance>,
-    units_metadata_attacker: &HashMap<i16, BattleUnitInfo>,
-    units_metadata_defender: &HashMap<i16, BattleUnitInfo>,
+    attackers: &mut UnitStore<P>,
+    defenders: &mut UnitStore<P>,
+    rules: &BattleRules,
 ) {
+    let regeneration_percentage = rules.shield_regeneration_percentage;
+
     // -------
     // Cleanup attacker units.
     // -------
     // First remove destroyed units.
-    attackers.retain(|unit| {
-        // Check if unit is fully destroyed.
-        if unit.current_hull_plating <= 0.0 {
-            increment_battle_unit_count_amount(&mut round.attacker_losses_in_round, unit.unit_id, 1);
-            return false;
-        }
-
-        true
+    attackers.remove_destroyed(|group| {
+        increment_battle_unit_count_amount(&mut round.attacker_losses_in_round, group.unit_id, 1);
+        let participant_round = &mut round.attacker_participants[group.participant_idx as usize];
+        increment_battle_unit_count_amount(&mut participant_round.losses_in_round, group.unit_id, 1);
     });
 
     // Then update shields in separate pass
-    for unit in attackers.iter_mut() {
-        let unit_metadata = units_metadata_attacker.get(&unit.unit_id).unwrap();
-        unit.current_shield_points = unit_metadata.shield_points;
-    }
+    attackers.regenerate_shields(regeneration_percentage);
 
     // -------
     // Cleanup defender units.
     // -------
     // First remove destroyed units.
-    defenders.retain(|unit| {
-        // Check if unit is fully destroyed.
-        if unit.current_hull_plating <= 0.0 {
-            increment_battle_unit_count_amount(&mut round.defender_losses_in_round, unit.unit_id, 1);
-            return false;
-        }
-
-        true
+    defenders.remove_destroyed(|group| {
+        increment_battle_unit_count_amount(&mut round.defender_losses_in_round, group.unit_id, 1);
+        let participant_round = &mut round.defender_participants[group.participant_idx as usize];
+        increment_battle_unit_count_amount(&mut participant_round.losses_in_round, group.unit_id, 1);
     });
 
     // Then update shields in separate pass for remaining units.
-    for unit in defenders.iter_mut() {
-        let unit_metadata = units_metadata_defender.get(&unit.unit_id).unwrap();
-        unit.current_shield_points = unit_metadata.shield_points;
+    defenders.regenerate_shields(regeneration_percentage);
+}
+
+/// Roll for every destroyed defense unit of the defender whether it is repaired after the battle.
+///
+/// Every unit has an individual chance of `defense_repair_percentage` (70% by default) to be repaired.
+/// Whether a unit is a defense is resolved per participant, see `BattleUnitInfo::resolve_unit_type`.
+fn repair_defenses(
+    defenders: &[BattleParticipant],
+    defender_rounds: &[ParticipantRound],
+    rules: &BattleRules,
+    rng: &mut BattleRng,
+) -> HashMap<i16, BattleUnitCount> {
+    // Combine the destroyed defenses of all participants, with the unit type of every participant.
+    let mut destroyed_defenses = HashMap::new();
+    for (participant, participant_round) in defenders.iter().zip(defender_rounds) {
+        for lost in participant_round.losses.values() {
+            let is_defense = participant
+                .units
+                .get(&lost.unit_id)
+                .is_some_and(|unit| unit.resolve_unit_type(rules) == UnitType::Defense);
+            if is_defense {
+                increment_battle_unit_count_amount(&mut destroyed_defenses, lost.unit_id, lost.amount);
+            }
+        }
+    }
+
+    // Roll in order of unit id so seeded battles stay reproducible.
+    let mut destroyed_defenses: Vec<BattleUnitCount> = destroyed_defenses.into_values().collect();
+    destroyed_defenses.sort_by_key(|unit| unit.unit_id);
+
+    let mut repaired_defenses = HashMap::new();
+    for unit in destroyed_defenses {
+        let repaired_amount = (0..unit.amount)
+            .filter(|_| rng.gen_range(0.0..100.0) < rules.defense_repair_percentage)
+            .count() as u32;
+
+        if repaired_amount > 0 {
+            increment_battle_unit_count_amount(&mut repaired_defenses, unit.unit_id, repaired_amount);
+        }
+    }
+
+    repaired_defenses
+}
+
+/// Calculate the debris of all losses until now when debris settings are provided.
+///
+/// The debris of the round is the difference with the debris until the previous round, so the rounds
+/// always add up to the total debris field. `debris` contains the total debris until the previous round
+/// and is updated to the total debris until this round.
+fn calculate_round_debris(round: &mut BattleRound, input: &BattleInput, debris: &mut Option<Resources>) {
+    if let (Some(settings), Some(previous_debris)) = (&input.debris, debris) {
+        let total_debris = debris::calculate_debris(
+            &input.attackers,
+            &input.defenders,
+            &round.attacker_participants,
+            &round.defender_participants,
+            settings,
+            &input.rules,
+        );
+        round.debris = Some(total_debris.saturating_sub(previous_debris));
+        *previous_debris = total_debris;
     }
 }
 
 /// Calculate the losses for the attacker and defender in this round compared to the starting
-/// units before the battle.
+/// units before the battle. Losses are calculated per participant and then summed up per side.
 fn calculate_losses(
     round: &mut BattleRound,
-    initial_attacker: &HashMap<i16, BattleUnitInfo>,
-    initial_defender: &HashMap<i16, BattleUnitInfo>,
+    initial_attackers: &[BattleParticipant],
+    initial_defenders: &[BattleParticipant],
 ) {
     // Calculate losses by comparing current counts with initial counts
-    for (_, unit) in initial_attacker {
+    for (participant, participant_round) in initial_attackers.iter().zip(round.attacker_participants.iter_mut()) {
+        calculate_participant_losses(participant, participant_round);
+    }
+    round.attacker_losses = merge_participant_counts(&round.attacker_participants, |participant| &participant.losses);
+
+    // Do the same for defender
+    for (participant, participant_round) in initial_defenders.iter().zip(round.defender_participants.iter_mut()) {
+        calculate_participant_losses(participant, participant_round);
+    }
+    round.defender_losses = merge_participant_counts(&round.defender_participants, |participant| &participant.losses);
+}
+
+/// Calculate the losses of a single participant compared to its starting units.
+fn calculate_participant_losses(participant: &BattleParticipant, participant_round: &mut ParticipantRound) {
+    for unit in participant.units.values() {
         let initial_count = unit.amount;
-        let current_count = round.attacker_ships.get(&unit.unit_id).map(|unit| unit.amount).unwrap_or(0);
+        let current_count = participant_round.ships.get(&unit.unit_id).map(|unit| unit.amount).unwrap_or(0);
 
         if current_count < initial_count {
             let loss_amount = initial_count - current_count;
-            increment_battle_unit_count_amount(&mut round.attacker_losses, unit.unit_id, loss_amount);
+            increment_battle_unit_count_amount(&mut participant_round.losses, unit.unit_id, loss_amount);
         }
     }
+}
 
-    // Do the same for defender
-    for (_, unit) in initial_defender {
-        let initial_count = unit.amount;
-        let current_count = round.defender_ships.get(&unit.unit_id).map(|unit| unit.amount).unwrap_or(0);
+/// Determine the outcome of the battle based on the units that are left after the last round.
+///
+/// This matches the winner that is shown in the PHP battle report, which lets the attacker win when
+/// no rounds were fought because one of the sides had no units, even when that is the attacker.
+fn determine_outcome(rounds_fought: usize, attackers_left: bool, defenders_left: bool) -> BattleOutcome {
+    match (attackers_left, defenders_left) {
+        _ if rounds_fought == 0 => BattleOutcome::AttackerWins,
+        (true, true) => BattleOutcome::Draw,
+        (true, false) => BattleOutcome::AttackerWins,
+        (false, _) => BattleOutcome::DefenderWins,
+    }
+}
 
-        if current_count < initial_count {
-            let loss_amount = initial_count - current_count;
-            increment_battle_unit_count_amount(&mut round.defender_losses, unit.unit_id, loss_amount);
+/// Create the final result of one side of the battle.
+///
+/// `participant_rounds` contains the last round of the participants of the side, or is empty when
+/// no rounds were fought.
+fn new_side_result(
+    surviving_units: HashMap<i16, BattleUnitCount>,
+    participants: &[BattleParticipant],
+    participant_rounds: &[ParticipantRound],
+) -> SideResult {
+    let lost_units = merge_participant_counts(participant_rounds, |participant| &participant.losses);
+
+    // The resource value is only known when every unit of the side has a cost. Participants can
+    // have different costs for the same unit, so the value is calculated per participant.
+    let all_units_have_cost = participants
+        .iter()
+        .flat_map(|participant| participant.units.values())
+        .all(|unit| unit.cost.is_some());
+    let mut lost_resources = all_units_have_cost.then(Resources::default);
+    if let Some(lost_resources) = &mut lost_resources {
+        for (participant, participant_round) in participants.iter().zip(participant_rounds) {
+            for lost in participant_round.losses.values() {
+                if let Some(cost) = participant.units.get(&lost.unit_id).and_then(|unit| unit.cost.as_ref()) {
+                    lost_resources.add_cost(cost, lost.amount as u64);
+                }
+            }
         }
     }
+
+    SideResult {
+        surviving_units,
+        lost_units,
+        lost_resources,
+    }
 }
 
 /// Helper method to increment the amount property of a BattleUnitCount struct.
-fn increment_battle_unit_count_amount(hash_map: &mut HashMap<i16, BattleUnitCount>, unit_id: i16, amount_to_increment: u32) {
+pub(crate) fn increment_battle_unit_count_amount(hash_map: &mut HashMap<i16, BattleUnitCount>, unit_id: i16, amount_to_increment: u32) {
     let count = hash_map.entry(unit_id).or_insert(BattleUnitCount {
         unit_id,
         amount: 0,
@@ -417,4 +1399,707 @@ fn update_peak_memory(current_peak: &mut u64) {
     if let Some(usage) = memory_stats() {
         *current_peak = (*current_peak).max(usage.physical_mem as u64 / 1024);
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEGACY: RuleSet = RuleSet::OgamexLegacy;
+    const OFFICIAL: RuleSet = RuleSet::OgameOfficial;
+
+    fn unit_group<P: Points>(shield_points: P, hull_plating: P) -> UnitGroup<P> {
+        UnitGroup {
+            participant_idx: 0,
+            unit_id: 401,
+            type_idx: 0,
+            attack_power: P::ZERO,
+            shield_points,
+            hull_plating,
+            pristine: 0,
+        }
+    }
+
+    /// Fire a single shot at a unit with the given current shield points and hull plating.
+    fn shoot<P: Points>(rule_set: RuleSet, damage: P, group: &UnitGroup<P>, state: (P, P), seed: u64) -> (Option<P>, P, P) {
+        let combat = CombatParams::new(&BattleRules::default(), rule_set);
+        let (mut current_shield_points, mut current_hull_plating) = state;
+        let mut rng = BattleRng::seed_from_u64(seed);
+        let shot = apply_shot(damage, &mut current_shield_points, &mut current_hull_plating, group, &combat, &mut rng);
+
+        (shot, current_shield_points, current_hull_plating)
+    }
+
+    /// Battle input of light fighters against rocket launchers, with the given amount of rocket launchers.
+    fn small_battle(defender_amount: u32) -> String {
+        format!(
+            r#"{{
+                "seed": 42,
+                "attacker_units": {{"204": {{"unit_id": 204, "amount": 100, "attack_power": 50, "shield_points": 10, "hull_plating": 400, "rapidfire": {{"401": 3}}}}}},
+                "defender_units": {{"401": {{"unit_id": 401, "amount": {}, "attack_power": 80, "shield_points": 20, "hull_plating": 200, "rapidfire": {{}}}}}}
+            }}"#,
+            defender_amount
+        )
+    }
+
+    /// Copy a string returned by the FFI interface and free it.
+    fn take_ffi_output(output: *mut c_char) -> serde_json::Value {
+        assert!(!output.is_null());
+        let output_json = unsafe { CStr::from_ptr(output) }.to_str().unwrap().to_owned();
+        unsafe { free_battle_output(output) };
+        serde_json::from_str(&output_json).unwrap()
+    }
+
+    #[test]
+    fn legacy_shots_bounce_off_original_shields() {
+        // 1% of the original shield points is 10, 1% of the current shield points is 1.
+        let group = unit_group(1000.0, 1000.0);
+
+        assert_eq!(shoot(LEGACY, 5.0, &group, (100.0, 1000.0), 0).0, None);
+        assert_eq!(shoot(OFFICIAL, 5.0, &group, (100.0, 1000.0), 0), (Some(5.0), 95.0, 1000.0));
+    }
+
+    #[test]
+    fn official_shots_hit_units_without_shields() {
+        let group = unit_group(1000.0, 1000.0);
+
+        assert_eq!(shoot(LEGACY, 5.0, &group, (0.0, 1000.0), 0).0, None);
+        assert_eq!(shoot(OFFICIAL, 5.0, &group, (0.0, 1000.0), 0), (Some(0.0), 0.0, 995.0));
+    }
+
+    #[test]
+    fn absorbed_shots_only_explode_units_with_legacy_rules() {
+        // The hull is at 10%, so every roll has a 90% chance to explode the unit.
+        let group = unit_group(1000.0, 1000.0);
+        let explosions = |rule_set| {
+            (0..100)
+                .filter(|seed| shoot(rule_set, 100.0, &group, (500.0, 100.0), *seed).2 <= 0.0)
+                .count()
+        };
+
+        assert!(explosions(LEGACY) > 50);
+        assert_eq!(explosions(OFFICIAL), 0);
+    }
+
+    #[test]
+    fn official_shots_through_shields_can_explode_units() {
+        let group = unit_group(1000.0, 1000.0);
+        let explosions = (0..100)
+            .filter(|seed| shoot(OFFICIAL, 100.0, &group, (50.0, 100.0), *seed).2 <= 0.0)
+            .count();
+
+        assert!(explosions > 50);
+    }
+
+    #[test]
+    fn bounced_shots_only_end_rapidfire_with_legacy_rules() {
+        // A single unit whose shots always bounce off, with a rapidfire chance of 99.9% against its target.
+        let mut input: BattleInput = serde_json::from_str(
+            r#"{
+                "attacker_units": {"204": {"unit_id": 204, "amount": 1, "attack_power": 1, "shield_points": 10, "hull_plating": 400, "rapidfire": {"401": 1000}}},
+                "defender_units": {"401": {"unit_id": 401, "amount": 1, "attack_power": 80, "shield_points": 1000, "hull_plating": 200, "rapidfire": {}}}
+            }"#,
+        )
+        .unwrap();
+        normalize_participants(&mut input);
+        let unit_types = UnitTypeIndex::new(&input.attackers, &input.defenders);
+
+        let fired = |rule_set| {
+            let attackers = UnitStore::<f32>::new(&input.attackers, &unit_types, UnitStrategy::Expanded, rule_set);
+            let mut defenders = UnitStore::<f32>::new(&input.defenders, &unit_types, UnitStrategy::Expanded, rule_set);
+            let mut round = new_battle_round(&input);
+            let mut rng = BattleRng::seed_from_u64(42);
+            let combat = CombatParams::new(&input.rules, rule_set);
+            let fired = process_combat(&attackers, &mut defenders, &mut round, &combat, true, &mut rng);
+
+            assert_eq!(round.hits_attacker, 0);
+            fired
+        };
+
+        assert_eq!(fired(LEGACY), 1);
+        assert!(fired(OFFICIAL) > 1);
+    }
+
+    #[test]
+    fn integer_shots_truncate_like_the_php_engine() {
+        let group = unit_group(10u32, 400u32);
+
+        // The hull plating can't drop below 0, the unit is destroyed either way.
+        assert_eq!(shoot(LEGACY, 1000u32, &group, (10, 400), 0), (Some(10), 0, 0));
+        // Damage that exceeds the shields goes to the hull plating.
+        assert_eq!(shoot(LEGACY, 7u32, &group, (5, 400), 0), (Some(5), 0, 398));
+    }
+
+    #[test]
+    fn integer_battle_rounds_have_whole_totals() {
+        let input: BattleInput = serde_json::from_str(
+            r#"{
+                "seed": 42,
+                "arithmetic": "integer",
+                "attacker_units": {"204": {"unit_id": 204, "amount": 1000, "attack_power": 55.9, "shield_points": 11.5, "hull_plating": 440, "rapidfire": {}}},
+                "defender_units": {"401": {"unit_id": 401, "amount": 1000, "attack_power": 88, "shield_points": 22, "hull_plating": 220, "rapidfire": {}}}
+            }"#,
+        )
+        .unwrap();
+        let output = process_battle_rounds(input).unwrap();
+
+        for round in &output.rounds {
+            // Stats are truncated to 55 damage per shot.
+            assert_eq!(round.full_strength_attacker, round.hits_attacker as f64 * 55.0);
+            assert_eq!(round.absorbed_damage_attacker.fract(), 0.0);
+            assert_eq!(round.absorbed_damage_defender.fract(), 0.0);
+        }
+    }
+
+    #[test]
+    fn rapidfire_above_the_maximum_is_rejected() {
+        let input = |rapidfire: u16| {
+            format!(
+                r#"{{
+                    "seed": 42,
+                    "attacker_units": {{"214": {{"unit_id": 214, "amount": 1, "attack_power": 200000, "shield_points": 50000, "hull_plating": 900000, "rapidfire": {{"210": {}}}}}}},
+                    "defender_units": {{"210": {{"unit_id": 210, "amount": 100, "attack_power": 0, "shield_points": 0, "hull_plating": 100, "rapidfire": {{}}}}}}
+                }}"#,
+                rapidfire
+            )
+        };
+
+        let error = parse_battle_input(input(20_000).as_bytes(), WireFormat::Json).err().unwrap();
+        assert_eq!(error.code, BattleErrorCode::InvalidInput);
+        assert_eq!(error.issues[0].field, "attacker_units.214.rapidfire.210");
+
+        // The highest allowed rapidfire still ends the rapidfire chain.
+        let battle_input = parse_battle_input(input(10_000).as_bytes(), WireFormat::Json).unwrap();
+        assert_eq!(process_battle_rounds(battle_input).unwrap().outcome, BattleOutcome::AttackerWins);
+    }
+
+    #[test]
+    fn base_stats_with_infinite_effective_stats_are_rejected() {
+        let input = |armor_technology: u32| {
+            format!(
+                r#"{{
+                    "attacker_units": {{"204": {{"unit_id": 204, "amount": 1, "base_stats": {{"attack": 50, "shield": 10, "structural_integrity": 3e38}}, "rapidfire": {{}}}}}},
+                    "attacker_technologies": {{"armor_technology": {}}},
+                    "defender_units": {{"401": {{"unit_id": 401, "amount": 1, "attack_power": 80, "shield_points": 20, "hull_plating": 200, "rapidfire": {{}}}}}}
+                }}"#,
+                armor_technology
+            )
+        };
+
+        // 1000 levels of armor technology give a structural integrity which doesn't fit in 32 bits.
+        let error = parse_battle_input(input(1000).as_bytes(), WireFormat::Json).err().unwrap();
+        assert_eq!(error.issues.len(), 1);
+        assert_eq!(error.issues[0].field, "attacker_units.204.base_stats.structural_integrity");
+        assert!(parse_battle_input(input(0).as_bytes(), WireFormat::Json).is_ok());
+    }
+
+    #[test]
+    fn effective_stats_are_validated_with_the_class_bonuses() {
+        let input = |technologies: &str| {
+            format!(
+                r#"{{
+                    "arithmetic": "integer",
+                    "attacker_units": {{"204": {{"unit_id": 204, "amount": 1, "attack_power": 50, "shield_points": 10, "hull_plating": 16000000, "rapidfire": {{}}}}}},
+                    "attacker_technologies": {},
+                    "defender_units": {{"401": {{"unit_id": 401, "amount": 1, "attack_power": 80, "shield_points": 20, "hull_plating": 200, "rapidfire": {{}}}}}}
+                }}"#,
+                technologies
+            )
+        };
+
+        // The 2 extra levels of armor technology of the General give a hull plating of 19,200,000.
+        let error = parse_battle_input(input(r#"{"player_class": "general"}"#).as_bytes(), WireFormat::Json).err().unwrap();
+        assert_eq!(error.issues.len(), 1);
+        assert_eq!(error.issues[0].field, "attacker_units.204.hull_plating");
+        assert!(parse_battle_input(input("{}").as_bytes(), WireFormat::Json).is_ok());
+    }
+
+    #[test]
+    fn estimate_with_unvalidated_rapidfire_does_not_overflow() {
+        let input: BattleInput = serde_json::from_str(
+            r#"{
+                "attacker_units": {"214": {"unit_id": 214, "amount": 1, "attack_power": 200000, "shield_points": 50000, "hull_plating": 900000, "rapidfire": {"210": 65535}}},
+                "defender_units": {"210": {"unit_id": 210, "amount": 100, "attack_power": 0, "shield_points": 0, "hull_plating": 100, "rapidfire": {}}}
+            }"#,
+        )
+        .unwrap();
+
+        let output = serde_json::to_value(estimate::estimate_battle_rounds(input)).unwrap();
+        assert_eq!(output["outcome"], serde_json::to_value(BattleOutcome::AttackerWins).unwrap());
+    }
+
+    #[test]
+    fn battles_with_the_same_seed_are_replayed() {
+        let fight = |seed: u64| {
+            let input = format!(
+                r#"{{
+                    "seed": {},
+                    "threads": 1,
+                    "attacker_units": {{"204": {{"unit_id": 204, "amount": 1000, "attack_power": 50, "shield_points": 10, "hull_plating": 400, "rapidfire": {{}}}}}},
+                    "defender_units": {{"401": {{"unit_id": 401, "amount": 1000, "attack_power": 80, "shield_points": 20, "hull_plating": 200, "rapidfire": {{}}}}}}
+                }}"#,
+                seed
+            );
+            let output = process_battle_rounds(serde_json::from_str(&input).unwrap()).unwrap();
+            serde_json::to_value(&output.rounds).unwrap()
+        };
+
+        assert_eq!(fight(42), fight(42));
+        assert_ne!(fight(42), fight(43));
+    }
+
+    #[test]
+    fn multi_threaded_battles_are_replayed_with_the_same_threads() {
+        let fight = |threads: Option<usize>| {
+            let mut input: BattleInput = serde_json::from_str(
+                r#"{
+                    "seed": 42,
+                    "attacker_units": {"204": {"unit_id": 204, "amount": 1000, "attack_power": 50, "shield_points": 10, "hull_plating": 400, "rapidfire": {"401": 3}}},
+                    "defender_units": {"401": {"unit_id": 401, "amount": 1000, "attack_power": 80, "shield_points": 20, "hull_plating": 200, "rapidfire": {}}}
+                }"#,
+            )
+            .unwrap();
+            input.threads = threads;
+            let output = process_battle_rounds(input).unwrap();
+            assert_eq!(output.threads, threads.unwrap_or(1));
+            serde_json::to_value(&output.rounds).unwrap()
+        };
+
+        assert_eq!(fight(Some(4)), fight(Some(4)));
+        // A single thread uses the sequential engine, the same as without threads (the environment
+        // variable isn't set for tests).
+        assert_eq!(fight(Some(1)), fight(None));
+    }
+
+    #[test]
+    fn aggregated_units_give_statistically_the_same_battles_as_expanded_units() {
+        let lost_units = |unit_strategy: UnitStrategy| {
+            let mut attacker_lost = 0;
+            let mut defender_lost = 0;
+            for seed in 0..20 {
+                let mut input: BattleInput = serde_json::from_str(
+                    r#"{
+                        "threads": 1,
+                        "rules": {"max_rounds": 2},
+                        "attacker_units": {"204": {"unit_id": 204, "amount": 500, "attack_power": 50, "shield_points": 10, "hull_plating": 400, "rapidfire": {"401": 3}}},
+                        "defender_units": {"401": {"unit_id": 401, "amount": 1000, "attack_power": 80, "shield_points": 20, "hull_plating": 200, "rapidfire": {}}}
+                    }"#,
+                )
+                .unwrap();
+                input.seed = Some(seed);
+                input.unit_strategy = unit_strategy;
+
+                let output = process_battle_rounds(input).unwrap();
+                assert_eq!(output.unit_strategy, unit_strategy);
+                attacker_lost += output.attacker.lost_units.get(&204).map_or(0, |unit| unit.amount);
+                defender_lost += output.defender.lost_units.get(&401).map_or(0, |unit| unit.amount);
+            }
+
+            (attacker_lost as f64, defender_lost as f64)
+        };
+
+        let (expanded_attacker_lost, expanded_defender_lost) = lost_units(UnitStrategy::Expanded);
+        let (aggregated_attacker_lost, aggregated_defender_lost) = lost_units(UnitStrategy::Aggregated);
+        assert!((aggregated_attacker_lost / expanded_attacker_lost - 1.0).abs() < 0.05);
+        assert!((aggregated_defender_lost / expanded_defender_lost - 1.0).abs() < 0.05);
+    }
+
+    #[test]
+    fn base_stats_give_the_same_battle_as_effective_stats() {
+        let fight = |input: &str| {
+            let output = process_battle_rounds(serde_json::from_str(input).unwrap()).unwrap();
+            serde_json::to_value(&output.rounds).unwrap()
+        };
+
+        let effective_stats = fight(
+            r#"{
+                "seed": 42,
+                "attacker_units": {"204": {"unit_id": 204, "amount": 1000, "attack_power": 70, "shield_points": 12, "hull_plating": 480, "rapidfire": {}}},
+                "defender_units": {"401": {"unit_id": 401, "amount": 1000, "attack_power": 80, "shield_points": 20, "hull_plating": 200, "rapidfire": {}}}
+            }"#,
+        );
+        let base_stats = fight(
+            r#"{
+                "seed": 42,
+                "attacker_units": {"204": {"unit_id": 204, "amount": 1000, "base_stats": {"attack": 50, "shield": 10, "structural_integrity": 4000}, "rapidfire": {}}},
+                "attacker_technologies": {"weapon_technology": 4, "shielding_technology": 2, "armor_technology": 2},
+                "defender_units": {"401": {"unit_id": 401, "amount": 1000, "base_stats": {"attack": 80, "shield": 20, "structural_integrity": 2000}, "rapidfire": {}}}
+            }"#,
+        );
+
+        assert_eq!(base_stats, effective_stats);
+    }
+
+    #[test]
+    fn class_bonuses_apply_to_effective_stats() {
+        let fight = |input: &str| {
+            let output = process_battle_rounds(serde_json::from_str(input).unwrap()).unwrap();
+            serde_json::to_value(&output.rounds).unwrap()
+        };
+
+        // The General gets 2 extra levels of every combat research, so the attacker fights with the
+        // stats of weapon technology 6, shielding technology 4 and armor technology 4.
+        let effective_stats_input = r#"{
+                "seed": 42,
+                "attacker_units": {"204": {"unit_id": 204, "amount": 1000, "attack_power": 70, "shield_points": 12, "hull_plating": 480, "rapidfire": {}}},
+                "attacker_technologies": {"weapon_technology": 4, "shielding_technology": 2, "armor_technology": 2, "player_class": "general"},
+                "defender_units": {"401": {"unit_id": 401, "amount": 1000, "attack_power": 80, "shield_points": 20, "hull_plating": 200, "rapidfire": {}}}
+            }"#;
+        let effective_stats = fight(effective_stats_input);
+        let base_stats = fight(
+            r#"{
+                "seed": 42,
+                "attacker_units": {"204": {"unit_id": 204, "amount": 1000, "base_stats": {"attack": 50, "shield": 10, "structural_integrity": 4000}, "rapidfire": {}}},
+                "attacker_technologies": {"weapon_technology": 6, "shielding_technology": 4, "armor_technology": 4},
+                "defender_units": {"401": {"unit_id": 401, "amount": 1000, "attack_power": 80, "shield_points": 20, "hull_plating": 200, "rapidfire": {}}}
+            }"#,
+        );
+
+        assert_eq!(effective_stats, base_stats);
+        assert_ne!(effective_stats, fight(&effective_stats_input.replace(r#", "player_class": "general""#, "")));
+    }
+
+    #[test]
+    fn defender_ships_flee_from_a_much_stronger_attacker() {
+        let fight = |ratio: f64| {
+            let input = format!(
+                r#"{{
+                    "seed": 42,
+                    "flee": {{"ratio": {}, "strength": "combat_value"}},
+                    "attacker_units": {{"204": {{"unit_id": 204, "amount": 1000, "attack_power": 50, "shield_points": 10, "hull_plating": 400, "rapidfire": {{}}}}}},
+                    "defender_units": {{
+                        "204": {{"unit_id": 204, "amount": 10, "attack_power": 50, "shield_points": 10, "hull_plating": 400, "rapidfire": {{}}}},
+                        "401": {{"unit_id": 401, "amount": 10, "attack_power": 80, "shield_points": 20, "hull_plating": 200, "rapidfire": {{}}}}
+                    }}
+                }}"#,
+                ratio
+            );
+            process_battle_rounds(serde_json::from_str(&input).unwrap()).unwrap()
+        };
+
+        // The attacker is about 60 times as strong, so the ships flee and the defenses stay.
+        let output = fight(50.0);
+        assert_eq!(output.fled_units.units[&204].amount, 10);
+        assert!(!output.fled_units.units.contains_key(&401));
+        assert!(!output.rounds[0].defender_ships.contains_key(&204));
+        assert!(!output.defender.lost_units.contains_key(&204));
+
+        let output = fight(100.0);
+        assert!(output.fled_units.units.is_empty());
+        assert!(output.fled_units.participants.is_empty());
+        assert_eq!(output.defender.lost_units[&204].amount, 10);
+    }
+
+    #[test]
+    fn invalid_battle_of_a_batch_only_fails_that_battle() {
+        let input = format!(
+            r#"{{"threads": 2, "battles": [{{"id": 1, "input": {}}}, {{"id": 2, "input": {}}}, {{"id": 3, "input": {}}}]}}"#,
+            small_battle(50),
+            small_battle(0),
+            small_battle(100)
+        );
+        let input = CString::new(input).unwrap();
+        let output = take_ffi_output(unsafe { fight_battles_batch(input.as_ptr()) });
+
+        let results = output["results"].as_array().unwrap();
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0]["id"], 1);
+        assert_eq!(results[0]["output"]["seed"], 42);
+        assert!(results[0].get("error").is_none());
+        assert_eq!(results[1]["id"], 2);
+        assert_eq!(results[1]["error"]["code"], "invalid_input");
+        assert!(results[1].get("output").is_none());
+        assert_eq!(results[2]["id"], 3);
+        assert_eq!(results[2]["output"]["seed"], 42);
+    }
+
+    #[test]
+    fn message_pack_battles_give_the_same_output_as_json() {
+        let fight_encoded = |input: &[u8], format: u32| {
+            let mut output_len = 0;
+            let output = unsafe { fight_battle_rounds_encoded(input.as_ptr(), input.len(), format, &mut output_len) };
+            assert!(!output.is_null());
+            let output_buffer = unsafe { std::slice::from_raw_parts(output, output_len) }.to_vec();
+            unsafe { free_battle_output_buffer(output, output_len) };
+            output_buffer
+        };
+
+        let json_input = small_battle(50);
+        let battle_input: BattleInput = serde_json::from_str(&json_input).unwrap();
+        let message_pack_input = rmp_serde::to_vec_named(&battle_input).unwrap();
+
+        let json_output: serde_json::Value = serde_json::from_slice(&fight_encoded(json_input.as_bytes(), 0)).unwrap();
+        let message_pack_output: BattleOutput = rmp_serde::from_slice(&fight_encoded(&message_pack_input, 1)).unwrap();
+        let message_pack_output = serde_json::to_value(&message_pack_output).unwrap();
+        for field in ["seed", "outcome", "rounds_fought", "attacker", "defender", "rounds"] {
+            assert_eq!(message_pack_output[field], json_output[field], "{}", field);
+        }
+
+        // Errors are encoded in the format of the input.
+        let error: serde_json::Value = rmp_serde::from_slice(&fight_encoded(&[0xc1], 1)).unwrap();
+        assert_eq!(error["error"]["code"], "invalid_message_pack");
+        let error: serde_json::Value = serde_json::from_slice(&fight_encoded(json_input.as_bytes(), 2)).unwrap();
+        assert_eq!(error["error"]["field"], "format");
+    }
+
+    #[test]
+    fn native_output_has_the_remaining_units_of_every_round() {
+        let attacker_units = [
+            NativeUnit { unit_id: 204, amount: 100, attack_power: 50.0, shield_points: 10.0, hull_plating: 400.0 },
+            NativeUnit { unit_id: 206, amount: 20, attack_power: 400.0, shield_points: 50.0, hull_plating: 2700.0 },
+        ];
+        let defender_units = [NativeUnit { unit_id: 401, amount: 200, attack_power: 80.0, shield_points: 20.0, hull_plating: 200.0 }];
+        let rapidfire = [NativeRapidfire { unit_id: 206, target_unit_id: 401, amount: 10 }];
+        let native_input = |rule_set: u32| NativeBattleInput {
+            attacker_units: attacker_units.as_ptr(),
+            attacker_unit_count: attacker_units.len(),
+            defender_units: defender_units.as_ptr(),
+            defender_unit_count: defender_units.len(),
+            rapidfire: rapidfire.as_ptr(),
+            rapidfire_count: rapidfire.len(),
+            rules: std::ptr::null(),
+            seed: 42,
+            use_seed: 1,
+            unit_strategy: 0,
+            threads: 1,
+            rule_set,
+            arithmetic: 0,
+        };
+
+        // The same battle as JSON.
+        let json_output = process_battle_rounds(
+            serde_json::from_str(
+                r#"{
+                    "seed": 42,
+                    "threads": 1,
+                    "attacker_units": {
+                        "204": {"unit_id": 204, "amount": 100, "attack_power": 50, "shield_points": 10, "hull_plating": 400, "rapidfire": {}},
+                        "206": {"unit_id": 206, "amount": 20, "attack_power": 400, "shield_points": 50, "hull_plating": 2700, "rapidfire": {"401": 10}}
+                    },
+                    "defender_units": {"401": {"unit_id": 401, "amount": 200, "attack_power": 80, "shield_points": 20, "hull_plating": 200, "rapidfire": {}}}
+                }"#,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+        let output = unsafe { fight_battle_rounds_native(&native_input(0)) };
+        let native_output = unsafe { &*output };
+        assert!(native_output.error.is_null());
+        assert_eq!(native_output.seed, 42);
+        assert_eq!(native_output.threads, 1);
+        assert_eq!(native_output.rounds_fought, json_output.rounds_fought);
+        assert_eq!(native_output.attacker_unit_count, 2);
+        assert_eq!(native_output.defender_unit_count, 1);
+
+        let rounds_fought = native_output.rounds_fought as usize;
+        let rounds = unsafe { std::slice::from_raw_parts(native_output.rounds, rounds_fought) };
+        let attacker_counts = unsafe { std::slice::from_raw_parts(native_output.attacker_counts, rounds_fought * 2) };
+        let defender_counts = unsafe { std::slice::from_raw_parts(native_output.defender_counts, rounds_fought) };
+        for (round_idx, round) in json_output.rounds.iter().enumerate() {
+            assert_eq!(rounds[round_idx].hits_attacker, round.hits_attacker);
+            assert_eq!(rounds[round_idx].hits_defender, round.hits_defender);
+            for (unit_idx, unit) in attacker_units.iter().enumerate() {
+                let amount = round.attacker_ships.get(&unit.unit_id).map_or(0, |count| count.amount);
+                assert_eq!(attacker_counts[round_idx * 2 + unit_idx], amount);
+            }
+            assert_eq!(defender_counts[round_idx], round.defender_ships.get(&401).map_or(0, |count| count.amount));
+        }
+        unsafe { free_battle_output_native(output) };
+
+        // An error only sets the error envelope.
+        let output = unsafe { fight_battle_rounds_native(&native_input(5)) };
+        let native_output = unsafe { &*output };
+        let error: serde_json::Value = serde_json::from_str(unsafe { CStr::from_ptr(native_output.error) }.to_str().unwrap()).unwrap();
+        assert_eq!(error["error"]["issues"][0]["field"], "rule_set");
+        assert_eq!(native_output.rounds_fought, 0);
+        assert!(native_output.rounds.is_null());
+        assert!(native_output.attacker_counts.is_null());
+        assert!(native_output.defender_counts.is_null());
+        assert!(native_output.repaired_defenses.is_null());
+        unsafe { free_battle_output_native(output) };
+    }
+
+    #[test]
+    fn streaming_callback_can_stop_the_battle() {
+        extern "C" fn stop_after_two_rounds(round_json: *const c_char, round_number: u32, user_data: *mut c_void) -> bool {
+            let round: serde_json::Value = serde_json::from_str(unsafe { CStr::from_ptr(round_json) }.to_str().unwrap()).unwrap();
+            assert!(round["attacker_ships"].is_object());
+            unsafe { *(user_data as *mut u32) = round_number };
+            round_number < 2
+        }
+
+        let input = CString::new(small_battle(200)).unwrap();
+        let output = take_ffi_output(unsafe { fight_battle_rounds_streaming(input.as_ptr(), None, std::ptr::null_mut()) });
+        assert!(output["rounds_fought"].as_u64().unwrap() > 2);
+        assert_eq!(output["stopped_early"], false);
+
+        let mut rounds_received: u32 = 0;
+        let output = take_ffi_output(unsafe {
+            fight_battle_rounds_streaming(input.as_ptr(), Some(stop_after_two_rounds), &mut rounds_received as *mut u32 as *mut c_void)
+        });
+        assert_eq!(rounds_received, 2);
+        assert_eq!(output["stopped_early"], true);
+        assert_eq!(output["rounds_fought"], 2);
+        assert_eq!(output["rounds"].as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn destroyed_defenses_are_repaired() {
+        let fight = |defense_repair_percentage: f32| {
+            let input = format!(
+                r#"{{
+                    "seed": 42,
+                    "rules": {{"defense_repair_percentage": {}}},
+                    "attacker_units": {{"206": {{"unit_id": 206, "amount": 1000, "attack_power": 400, "shield_points": 50, "hull_plating": 2700, "rapidfire": {{}}}}}},
+                    "defender_units": {{
+                        "202": {{"unit_id": 202, "amount": 100, "unit_type": "defense", "attack_power": 5, "shield_points": 10, "hull_plating": 400, "rapidfire": {{}}}},
+                        "203": {{"unit_id": 203, "amount": 100, "attack_power": 5, "shield_points": 25, "hull_plating": 1200, "rapidfire": {{}}}},
+                        "401": {{"unit_id": 401, "amount": 100, "attack_power": 80, "shield_points": 20, "hull_plating": 200, "rapidfire": {{}}}},
+                        "402": {{"unit_id": 402, "amount": 100, "unit_type": "ship", "attack_power": 100, "shield_points": 25, "hull_plating": 200, "rapidfire": {{}}}}
+                    }}
+                }}"#,
+                defense_repair_percentage
+            );
+            process_battle_rounds(serde_json::from_str(&input).unwrap()).unwrap()
+        };
+
+        let output = fight(0.0);
+        assert_eq!(output.defender.lost_units.len(), 4);
+        assert!(output.repaired_defenses.is_empty());
+
+        // Only units which are defenses by their unit type or the defense unit id range are repaired.
+        let output = fight(100.0);
+        let mut repaired: Vec<(i16, u32)> = output.repaired_defenses.values().map(|unit| (unit.unit_id, unit.amount)).collect();
+        repaired.sort();
+        assert_eq!(repaired, vec![(202, 100), (401, 100)]);
+    }
+
+    #[test]
+    fn outcome_depends_on_the_units_left_after_the_last_round() {
+        assert_eq!(determine_outcome(6, true, false), BattleOutcome::AttackerWins);
+        assert_eq!(determine_outcome(6, false, true), BattleOutcome::DefenderWins);
+        assert_eq!(determine_outcome(6, false, false), BattleOutcome::DefenderWins);
+        assert_eq!(determine_outcome(6, true, true), BattleOutcome::Draw);
+        assert_eq!(determine_outcome(0, false, true), BattleOutcome::AttackerWins);
+
+        let fight = |attacker_amount: u32, max_rounds: u32| {
+            let attacker_units = match attacker_amount {
+                0 => String::new(),
+                amount => format!(
+                    r#""204": {{"unit_id": 204, "amount": {}, "attack_power": 50, "shield_points": 10, "hull_plating": 400, "rapidfire": {{}}}}"#,
+                    amount
+                ),
+            };
+            let input = format!(
+                r#"{{
+                    "seed": 42,
+                    "rules": {{"max_rounds": {}}},
+                    "attacker_units": {{{}}},
+                    "defender_units": {{"401": {{"unit_id": 401, "amount": 100, "attack_power": 80, "shield_points": 20, "hull_plating": 200, "rapidfire": {{}}}}}}
+                }}"#,
+                max_rounds, attacker_units
+            );
+            process_battle_rounds(serde_json::from_str(&input).unwrap()).unwrap()
+        };
+
+        assert_eq!(fight(10_000, 6).outcome, BattleOutcome::AttackerWins);
+        assert_eq!(fight(1, 6).outcome, BattleOutcome::DefenderWins);
+        assert_eq!(fight(100, 1).outcome, BattleOutcome::Draw);
+
+        // Without attacking units no rounds are fought, which the PHP battle report shows as a win of
+        // the attacker.
+        let output = fight(0, 6);
+        assert_eq!(output.rounds_fought, 0);
+        assert_eq!(output.outcome, BattleOutcome::AttackerWins);
+    }
+
+    #[test]
+    fn lost_resources_of_very_expensive_units_are_capped() {
+        let participants: Vec<BattleParticipant> = serde_json::from_str(
+            r#"[{"participant_id": 1, "units": {
+                "204": {"unit_id": 204, "amount": 3, "cost": {"metal": 18446744073709551615, "crystal": 1000, "deuterium": 0}, "rapidfire": {}},
+                "206": {"unit_id": 206, "amount": 3, "cost": {"metal": 18446744073709551615, "crystal": 1000, "deuterium": 0}, "rapidfire": {}}
+            }}]"#,
+        )
+        .unwrap();
+        let participant_rounds: Vec<ParticipantRound> = serde_json::from_str(
+            r#"[{"participant_id": 1, "ships": {}, "losses_in_round": {}, "losses": {
+                "204": {"unit_id": 204, "amount": 3},
+                "206": {"unit_id": 206, "amount": 2}
+            }}]"#,
+        )
+        .unwrap();
+
+        let result = new_side_result(HashMap::new(), &participants, &participant_rounds);
+        let lost_resources = result.lost_resources.unwrap();
+        assert_eq!((lost_resources.metal, lost_resources.crystal), (u64::MAX, 5_000));
+    }
+
+    #[test]
+    fn losses_of_participants_add_up_to_the_losses_of_their_side() {
+        let input = r#"{
+            "seed": 42,
+            "attackers": [
+                {"participant_id": 10, "units": {"204": {"unit_id": 204, "amount": 300, "attack_power": 50, "shield_points": 10, "hull_plating": 400, "rapidfire": {}}}},
+                {"participant_id": 11, "units": {
+                    "204": {"unit_id": 204, "amount": 200, "attack_power": 60, "shield_points": 10, "hull_plating": 400, "rapidfire": {}},
+                    "206": {"unit_id": 206, "amount": 50, "attack_power": 400, "shield_points": 50, "hull_plating": 2700, "rapidfire": {"401": 10}}
+                }}
+            ],
+            "defenders": [
+                {"participant_id": 20, "units": {"401": {"unit_id": 401, "amount": 400, "attack_power": 80, "shield_points": 20, "hull_plating": 200, "rapidfire": {}}}},
+                {"participant_id": 21, "units": {"204": {"unit_id": 204, "amount": 100, "attack_power": 50, "shield_points": 10, "hull_plating": 400, "rapidfire": {}}}}
+            ]
+        }"#;
+        let output = process_battle_rounds(serde_json::from_str(input).unwrap()).unwrap();
+
+        for round in &output.rounds {
+            let sides = [
+                (&round.attacker_participants, &round.attacker_losses, vec![10, 11]),
+                (&round.defender_participants, &round.defender_losses, vec![20, 21]),
+            ];
+            for (participants, side_losses, participant_ids) in sides {
+                let ids: Vec<u64> = participants.iter().map(|participant| participant.participant_id).collect();
+                assert_eq!(ids, participant_ids);
+
+                let mut losses: HashMap<i16, u32> = HashMap::new();
+                for participant in participants {
+                    for lost in participant.losses.values() {
+                        *losses.entry(lost.unit_id).or_insert(0) += lost.amount;
+                    }
+                }
+                let side_losses: HashMap<i16, u32> = side_losses.values().map(|lost| (lost.unit_id, lost.amount)).collect();
+                assert_eq!(losses, side_losses);
+            }
+        }
+
+        // Both attackers lost light fighters, which are counted against their own fleet.
+        let last_round = output.rounds.last().unwrap();
+        let lost_fighters = |participant: &ParticipantRound| participant.losses.get(&204).map_or(0, |lost| lost.amount);
+        assert!(lost_fighters(&last_round.attacker_participants[0]) > 0);
+        assert!(lost_fighters(&last_round.attacker_participants[1]) > 0);
+        assert!(lost_fighters(&last_round.attacker_participants[0]) <= 300);
+        assert!(lost_fighters(&last_round.attacker_participants[1]) <= 200);
+    }
+
+    #[test]
+    fn too_many_rounds_are_rejected() {
+        let input = |max_rounds: u32| {
+            format!(
+                r#"{{
+                    "rules": {{"max_rounds": {}}},
+                    "attacker_units": {{"204": {{"unit_id": 204, "amount": 1, "attack_power": 50, "shield_points": 10, "hull_plating": 400, "rapidfire": {{}}}}}},
+                    "defender_units": {{"401": {{"unit_id": 401, "amount": 1, "attack_power": 80, "shield_points": 20, "hull_plating": 200, "rapidfire": {{}}}}}}
+                }}"#,
+                max_rounds
+            )
+        };
+
+        let error = parse_battle_input(input(0).as_bytes(), WireFormat::Json).err().unwrap();
+        assert_eq!(error.issues[0].field, "rules.max_rounds");
+        let error = parse_battle_input(input(rules::MAX_ROUNDS + 1).as_bytes(), WireFormat::Json).err().unwrap();
+        assert_eq!(error.issues[0].field, "rules.max_rounds");
+        assert!(parse_battle_input(input(rules::MAX_ROUNDS).as_bytes(), WireFormat::Json).is_ok());
+    }
+}
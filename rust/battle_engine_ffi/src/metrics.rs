@@ -0,0 +1,236 @@
+use serde::{Serialize, Serializer};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicI64, AtomicU64, AtomicUsize, Ordering};
+use std::time::Instant;
+
+/// Global allocator which counts the allocations of the engine while metrics are recorded.
+///
+/// The PHP client uses its own allocator, so only memory allocated by the engine is counted. When
+/// no metrics are recorded the only overhead is a single atomic load per allocation.
+pub struct CountingAllocator;
+
+/// Amount of metrics recorders that are alive. Allocations are counted while it is above 0.
+static ACTIVE_RECORDERS: AtomicUsize = AtomicUsize::new(0);
+static ALLOCATED_BYTES: AtomicU64 = AtomicU64::new(0);
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+// Live bytes can become negative when memory allocated before the recording started is freed.
+static LIVE_BYTES: AtomicI64 = AtomicI64::new(0);
+static PEAK_LIVE_BYTES: AtomicI64 = AtomicI64::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            record_allocation(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc_zeroed(layout);
+        if !ptr.is_null() {
+            record_allocation(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        record_deallocation(layout.size());
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            // A reallocation is counted as a new allocation which frees the old one.
+            record_allocation(new_size);
+            record_deallocation(layout.size());
+        }
+        new_ptr
+    }
+}
+
+fn record_allocation(size: usize) {
+    if ACTIVE_RECORDERS.load(Ordering::Relaxed) == 0 {
+        return;
+    }
+
+    ALLOCATED_BYTES.fetch_add(size as u64, Ordering::Relaxed);
+    ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    let live_bytes = LIVE_BYTES.fetch_add(size as i64, Ordering::Relaxed) + size as i64;
+    PEAK_LIVE_BYTES.fetch_max(live_bytes, Ordering::Relaxed);
+}
+
+fn record_deallocation(size: usize) {
+    if ACTIVE_RECORDERS.load(Ordering::Relaxed) == 0 {
+        return;
+    }
+
+    LIVE_BYTES.fetch_sub(size as i64, Ordering::Relaxed);
+}
+
+/// Metrics of a single battle, returned as `metrics` in the battle output when requested.
+///
+/// Allocations are counted process wide, so the metrics are only accurate when a single battle is
+/// processed at a time, which is always the case for PHP workers. Battles which are recorded at the
+/// same time share the counters, which are only reset when no other battle is recorded.
+#[derive(Serialize)]
+pub struct BattleMetrics<'a> {
+    /// Total amount of bytes allocated by the engine.
+    allocated_bytes: u64,
+    /// Amount of allocations made by the engine.
+    allocations: u64,
+    /// Highest amount of bytes allocated by the engine at the same time.
+    peak_live_bytes: u64,
+    /// Wall-clock time of every phase of the battle.
+    timings: PhaseTimings,
+    /// Duration and shots of every round.
+    rounds: &'a [RoundMetrics],
+}
+
+/// Wall-clock time of every phase of the battle in microseconds.
+#[derive(Serialize, Default, Clone, Copy)]
+struct PhaseTimings {
+    /// Creating the units of both sides.
+    expand_us: u64,
+    /// Fighting all rounds.
+    rounds_us: u64,
+    /// Turning the remaining units back into unit counts: defense repairs, moon and side results.
+    compress_us: u64,
+    /// Serializing the battle output to JSON.
+    serialize_us: u64,
+    /// Total time of all phases.
+    total_us: u64,
+}
+
+/// Metrics of a single round.
+#[derive(Serialize)]
+struct RoundMetrics {
+    /// Wall-clock time of the round in microseconds.
+    duration_us: u64,
+    /// Shots fired by the attacker, including bounced shots and rapidfire.
+    shots_attacker: u64,
+    /// Shots fired by the defender, including bounced shots and rapidfire.
+    shots_defender: u64,
+}
+
+/// Records the metrics of a battle while it is processed. Does nothing when metrics are disabled.
+pub struct MetricsRecorder {
+    enabled: bool,
+    started: Instant,
+    phase_started: Instant,
+    timings: PhaseTimings,
+    rounds: Vec<RoundMetrics>,
+}
+
+impl MetricsRecorder {
+    /// Start recording metrics, which resets the allocation counters unless another recorder is alive.
+    pub fn start(enabled: bool) -> Self {
+        if enabled && ACTIVE_RECORDERS.fetch_add(1, Ordering::Relaxed) == 0 {
+            ALLOCATED_BYTES.store(0, Ordering::Relaxed);
+            ALLOCATIONS.store(0, Ordering::Relaxed);
+            LIVE_BYTES.store(0, Ordering::Relaxed);
+            PEAK_LIVE_BYTES.store(0, Ordering::Relaxed);
+        }
+
+        let now = Instant::now();
+        MetricsRecorder {
+            enabled,
+            started: now,
+            phase_started: now,
+            timings: PhaseTimings::default(),
+            rounds: Vec::new(),
+        }
+    }
+
+    /// Whether metrics are recorded.
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Record the end of creating the units.
+    pub fn record_expand(&mut self) {
+        if self.enabled {
+            self.timings.expand_us = self.lap();
+        }
+    }
+
+    /// Record the end of a round with the shots fired by both sides.
+    pub fn record_round(&mut self, shots_attacker: u64, shots_defender: u64) {
+        if self.enabled {
+            let duration_us = self.lap();
+            self.timings.rounds_us += duration_us;
+            self.rounds.push(RoundMetrics {
+                duration_us,
+                shots_attacker,
+                shots_defender,
+            });
+        }
+    }
+
+    /// Record the end of turning the remaining units back into unit counts.
+    pub fn record_compress(&mut self) {
+        if self.enabled {
+            self.timings.compress_us = self.lap();
+        }
+    }
+
+    /// Get the metrics recorded until now. This is called while the battle output is serialized, so
+    /// the time since the end of the last phase is recorded as the serialization time.
+    pub fn metrics(&self) -> BattleMetrics<'_> {
+        let mut timings = self.timings;
+        timings.serialize_us = self.phase_started.elapsed().as_micros() as u64;
+        timings.total_us = self.started.elapsed().as_micros() as u64;
+
+        BattleMetrics {
+            allocated_bytes: ALLOCATED_BYTES.load(Ordering::Relaxed),
+            allocations: ALLOCATIONS.load(Ordering::Relaxed),
+            peak_live_bytes: PEAK_LIVE_BYTES.load(Ordering::Relaxed).max(0) as u64,
+            timings,
+            rounds: &self.rounds,
+        }
+    }
+
+    /// Microseconds since the end of the previous phase.
+    fn lap(&mut self) -> u64 {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.phase_started).as_micros() as u64;
+        self.phase_started = now;
+        elapsed
+    }
+}
+
+/// Serialize the metrics of the recorder, used for the `metrics` field of the battle output.
+pub fn serialize_metrics<S: Serializer>(recorder: &Option<MetricsRecorder>, serializer: S) -> Result<S::Ok, S::Error> {
+    recorder.as_ref().map(MetricsRecorder::metrics).serialize(serializer)
+}
+
+impl Drop for MetricsRecorder {
+    fn drop(&mut self) {
+        // Stop counting allocations once the battle output is serialized or the battle failed, unless
+        // another battle is still recorded.
+        if self.enabled {
+            ACTIVE_RECORDERS.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allocations_are_counted_until_the_last_recorder_is_dropped() {
+        let first = MetricsRecorder::start(true);
+        let second = MetricsRecorder::start(true);
+        drop(first);
+
+        let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+        let buffer = std::hint::black_box(vec![0u8; 1024]);
+        assert!(ALLOCATIONS.load(Ordering::Relaxed) > allocations);
+        drop(buffer);
+
+        assert!(second.metrics().allocated_bytes >= 1024);
+        drop(second);
+    }
+}
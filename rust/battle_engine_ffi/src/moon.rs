@@ -0,0 +1,92 @@
+use crate::{BattleRng, Resources};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+/// Moon creation settings which are provided by the PHP client.
+///
+/// The moon chance is based on the debris field, so debris settings have to be provided as well.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct MoonSettings {
+    /// Whether the defender's planet already has a moon. No moon can be created in that case.
+    pub moon_exists: bool,
+    /// Maximum moon chance in percent. Mirrors the `maximum_moon_chance` server setting.
+    pub max_chance: u32,
+    /// Amount of debris that results in 1% moon chance.
+    pub debris_per_percent: u64,
+}
+
+impl Default for MoonSettings {
+    fn default() -> Self {
+        MoonSettings {
+            moon_exists: false,
+            max_chance: 20,
+            debris_per_percent: 100_000,
+        }
+    }
+}
+
+/// Calculate the moon chance in percent based on the debris field.
+///
+/// This works the same as `BattleEngine::calculateMoonChance` in PHP.
+pub fn calculate_moon_chance(debris: &Resources, settings: &MoonSettings) -> u32 {
+    if settings.moon_exists {
+        return 0;
+    }
+
+    let debris_sum = debris.metal.saturating_add(debris.crystal).saturating_add(debris.deuterium);
+    let chance = debris_sum / settings.debris_per_percent;
+    chance.min(settings.max_chance as u64) as u32
+}
+
+/// Roll the dice to see if a moon is created. No dice is rolled if a moon already exists, the same
+/// as in PHP.
+pub fn roll_moon_creation(moon_chance: u32, settings: &MoonSettings, rng: &mut BattleRng) -> bool {
+    if settings.moon_exists {
+        return false;
+    }
+
+    rng.gen_range(1..=100) <= moon_chance
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    #[test]
+    fn moon_chance_is_one_percent_per_debris_step_up_to_the_maximum() {
+        let debris = |metal: u64, crystal: u64| Resources {
+            metal,
+            crystal,
+            deuterium: 0,
+        };
+        let settings = MoonSettings::default();
+
+        assert_eq!(calculate_moon_chance(&debris(99_999, 0), &settings), 0);
+        assert_eq!(calculate_moon_chance(&debris(900_000, 334_567), &settings), 12);
+        assert_eq!(calculate_moon_chance(&debris(5_000_000, 0), &settings), 20);
+
+        let existing_moon = MoonSettings {
+            moon_exists: true,
+            ..MoonSettings::default()
+        };
+        assert_eq!(calculate_moon_chance(&debris(5_000_000, 0), &existing_moon), 0);
+    }
+
+    #[test]
+    fn moon_is_created_with_the_moon_chance() {
+        let settings = MoonSettings::default();
+        let mut rng = BattleRng::seed_from_u64(42);
+        for _ in 0..100 {
+            assert!(!roll_moon_creation(0, &settings, &mut rng));
+            assert!(roll_moon_creation(100, &settings, &mut rng));
+        }
+
+        let existing_moon = MoonSettings {
+            moon_exists: true,
+            ..MoonSettings::default()
+        };
+        assert!(!roll_moon_creation(100, &existing_moon, &mut rng));
+    }
+}
@@ -0,0 +1,320 @@
+//! Native C struct interface of the battle engine.
+//!
+//! The caller passes arrays of `#[repr(C)]` unit records instead of a JSON string and gets the
+//! remaining units of every round back as flat arrays, so no JSON is encoded or decoded for the
+//! largest battles. PHP FFI can read and write these structs directly.
+//!
+//! This interface only supports a single fleet per side without debris or moon calculation, use the
+//! JSON interface for those.
+use crate::error::{BattleError, BattleErrorCode, ValidationIssue};
+use crate::{
+    process_battle_rounds, validation, Arithmetic, BattleInput, BattleOutcome, BattleRules, BattleUnitInfo, RuleSet, UnitStrategy,
+};
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::os::raw::c_char;
+use std::ptr;
+
+/// Unit record of the battle input.
+#[repr(C)]
+pub struct NativeUnit {
+    pub unit_id: i16,
+    pub amount: u32,
+    pub attack_power: f32,
+    pub shield_points: f32,
+    pub hull_plating: f32,
+}
+
+/// Row of the rapidfire table: units with `unit_id` have rapidfire `amount` against units with
+/// `target_unit_id`. The table applies to the units of both sides.
+#[repr(C)]
+pub struct NativeRapidfire {
+    pub unit_id: i16,
+    pub target_unit_id: i16,
+    pub amount: u16,
+}
+
+/// Battle input of `fight_battle_rounds_native`.
+#[repr(C)]
+pub struct NativeBattleInput {
+    pub attacker_units: *const NativeUnit,
+    pub attacker_unit_count: usize,
+    pub defender_units: *const NativeUnit,
+    pub defender_unit_count: usize,
+    pub rapidfire: *const NativeRapidfire,
+    pub rapidfire_count: usize,
+    /// Combat rule parameters, or null for the default OGame rules.
+    pub rules: *const BattleRules,
+    /// Seed for the random number generator. Only used when `use_seed` is not 0, otherwise a random
+    /// seed is generated.
+    pub seed: u64,
+    /// Non-zero to use `seed`. This is an integer instead of a bool, as any other value than 0 or 1 in
+    /// a bool is undefined behavior.
+    pub use_seed: u32,
+    /// 0 for expanded and 1 for aggregated units, see `UnitStrategy`.
+    pub unit_strategy: u32,
+    /// Amount of threads, or 0 to use the `BATTLE_ENGINE_THREADS` environment variable.
+    pub threads: u32,
+    /// 0 for the legacy OGameX rules and 1 for the official OGame rules, see `RuleSet`.
+    pub rule_set: u32,
+    /// 0 for float and 1 for integer arithmetic, see `Arithmetic`.
+    pub arithmetic: u32,
+}
+
+/// Statistics of a single round.
+#[repr(C)]
+pub struct NativeRound {
+    pub hits_attacker: u32,
+    pub hits_defender: u32,
+    pub full_strength_attacker: f64,
+    pub full_strength_defender: f64,
+    pub absorbed_damage_attacker: f64,
+    pub absorbed_damage_defender: f64,
+}
+
+/// Battle output of `fight_battle_rounds_native`, which must be freed with
+/// `free_battle_output_native`.
+///
+/// The remaining units are stored per round in the order of the units in the battle input: the amount
+/// of the unit at index `u` of the attacker units at the end of round `r` is at
+/// `attacker_counts[r * attacker_unit_count + u]`.
+#[repr(C)]
+pub struct NativeBattleOutput {
+    /// JSON error envelope if the battle could not be processed, null otherwise. All arrays are null
+    /// when an error is returned.
+    pub error: *mut c_char,
+    pub seed: u64,
+    pub threads: u32,
+    /// 0 when the attacker wins, 1 when the defender wins and 2 for a draw, see `BattleOutcome`.
+    pub outcome: u32,
+    pub rounds_fought: u32,
+    /// Statistics of every round, `rounds_fought` entries.
+    pub rounds: *mut NativeRound,
+    pub attacker_unit_count: usize,
+    /// Remaining attacker units, `rounds_fought * attacker_unit_count` entries.
+    pub attacker_counts: *mut u32,
+    pub defender_unit_count: usize,
+    /// Remaining defender units, `rounds_fought * defender_unit_count` entries.
+    pub defender_counts: *mut u32,
+    /// Repaired defenses per defender unit, `defender_unit_count` entries.
+    pub repaired_defenses: *mut u32,
+}
+
+impl NativeBattleOutput {
+    /// Create an output which only contains the error envelope.
+    pub fn from_error(error: BattleError) -> Self {
+        NativeBattleOutput {
+            // serde_json escapes null bytes, so the error never contains an interior null byte.
+            error: CString::new(error.to_json()).unwrap_or_default().into_raw(),
+            seed: 0,
+            threads: 0,
+            outcome: 0,
+            rounds_fought: 0,
+            rounds: ptr::null_mut(),
+            attacker_unit_count: 0,
+            attacker_counts: ptr::null_mut(),
+            defender_unit_count: 0,
+            defender_counts: ptr::null_mut(),
+            repaired_defenses: ptr::null_mut(),
+        }
+    }
+
+    /// Free the error and arrays of the output.
+    ///
+    /// # Safety
+    ///
+    /// The output must have been created by this module and must not be freed twice.
+    pub unsafe fn free(self) {
+        if !self.error.is_null() {
+            drop(CString::from_raw(self.error));
+        }
+
+        let rounds_fought = self.rounds_fought as usize;
+        free_array(self.rounds, rounds_fought);
+        free_array(self.attacker_counts, rounds_fought * self.attacker_unit_count);
+        free_array(self.defender_counts, rounds_fought * self.defender_unit_count);
+        free_array(self.repaired_defenses, self.defender_unit_count);
+    }
+}
+
+/// Process the battle rounds for the native battle input.
+///
+/// # Safety
+///
+/// `input` must be null or a valid pointer to a battle input whose arrays are valid for the given counts.
+pub unsafe fn fight_battle_native(input: *const NativeBattleInput) -> Result<NativeBattleOutput, BattleError> {
+    let Some(input) = input.as_ref() else {
+        return Err(BattleError::new(BattleErrorCode::NullPointer, "input is a null pointer"));
+    };
+
+    let attacker_units = slice(input.attacker_units, input.attacker_unit_count, "attacker_units")?;
+    let defender_units = slice(input.defender_units, input.defender_unit_count, "defender_units")?;
+    let rapidfire = slice(input.rapidfire, input.rapidfire_count, "rapidfire")?;
+
+    let mut issues = Vec::new();
+    let unit_strategy = match input.unit_strategy {
+        0 => UnitStrategy::Expanded,
+        1 => UnitStrategy::Aggregated,
+        _ => {
+            issues.push(ValidationIssue::new("unit_strategy", "must be 0 (expanded) or 1 (aggregated)"));
+            UnitStrategy::Expanded
+        }
+    };
+    let rule_set = match input.rule_set {
+        0 => RuleSet::OgamexLegacy,
+        1 => RuleSet::OgameOfficial,
+        _ => {
+            issues.push(ValidationIssue::new("rule_set", "must be 0 (ogamex_legacy) or 1 (ogame_official)"));
+            RuleSet::OgamexLegacy
+        }
+    };
+    let arithmetic = match input.arithmetic {
+        0 => Arithmetic::Float,
+        1 => Arithmetic::Integer,
+        _ => {
+            issues.push(ValidationIssue::new("arithmetic", "must be 0 (float) or 1 (integer)"));
+            Arithmetic::Float
+        }
+    };
+
+    let battle_input = BattleInput {
+        attacker_units: new_units(attacker_units, rapidfire, "attacker_units", &mut issues),
+        defender_units: new_units(defender_units, rapidfire, "defender_units", &mut issues),
+        seed: (input.use_seed != 0).then_some(input.seed),
+        rules: input.rules.as_ref().cloned().unwrap_or_default(),
+        rule_set,
+        arithmetic,
+        unit_strategy,
+        threads: (input.threads > 0).then_some(input.threads as usize),
+        ..BattleInput::default()
+    };
+
+    // Problems with the native records are reported together with the regular validation issues.
+    if let Err(mut error) = validation::validate_battle_input(&battle_input, &[]) {
+        issues.append(&mut error.issues);
+    }
+    if !issues.is_empty() {
+        return Err(BattleError::from_issues(issues));
+    }
+
+    let battle_output = process_battle_rounds(battle_input)?;
+
+    let rounds: Vec<NativeRound> = battle_output
+        .rounds
+        .iter()
+        .map(|round| NativeRound {
+            hits_attacker: round.hits_attacker,
+            hits_defender: round.hits_defender,
+            full_strength_attacker: round.full_strength_attacker,
+            full_strength_defender: round.full_strength_defender,
+            absorbed_damage_attacker: round.absorbed_damage_attacker,
+            absorbed_damage_defender: round.absorbed_damage_defender,
+        })
+        .collect();
+
+    let mut attacker_counts = Vec::with_capacity(rounds.len() * attacker_units.len());
+    let mut defender_counts = Vec::with_capacity(rounds.len() * defender_units.len());
+    for round in &battle_output.rounds {
+        attacker_counts.extend(attacker_units.iter().map(|unit| round.attacker_ships.get(&unit.unit_id).map_or(0, |count| count.amount)));
+        defender_counts.extend(defender_units.iter().map(|unit| round.defender_ships.get(&unit.unit_id).map_or(0, |count| count.amount)));
+    }
+    let repaired_defenses: Vec<u32> = defender_units
+        .iter()
+        .map(|unit| battle_output.repaired_defenses.get(&unit.unit_id).map_or(0, |count| count.amount))
+        .collect();
+
+    Ok(NativeBattleOutput {
+        error: ptr::null_mut(),
+        seed: battle_output.seed,
+        threads: battle_output.threads as u32,
+        outcome: match battle_output.outcome {
+            BattleOutcome::AttackerWins => 0,
+            BattleOutcome::DefenderWins => 1,
+            BattleOutcome::Draw => 2,
+        },
+        rounds_fought: battle_output.rounds_fought,
+        rounds: into_array(rounds),
+        attacker_unit_count: attacker_units.len(),
+        attacker_counts: into_array(attacker_counts),
+        defender_unit_count: defender_units.len(),
+        defender_counts: into_array(defender_counts),
+        repaired_defenses: into_array(repaired_defenses),
+    })
+}
+
+/// Convert the unit records of one side into battle unit info with their rapidfire from the table.
+fn new_units(
+    units: &[NativeUnit],
+    rapidfire: &[NativeRapidfire],
+    side: &str,
+    issues: &mut Vec<ValidationIssue>,
+) -> HashMap<i16, BattleUnitInfo> {
+    let mut unit_infos = HashMap::with_capacity(units.len());
+    for (index, unit) in units.iter().enumerate() {
+        if unit_infos.contains_key(&unit.unit_id) {
+            issues.push(ValidationIssue::new(
+                format!("{}.{}.unit_id", side, index),
+                format!("duplicate unit_id {}", unit.unit_id),
+            ));
+            continue;
+        }
+
+        let unit_rapidfire = rapidfire
+            .iter()
+            .filter(|row| row.unit_id == unit.unit_id)
+            .map(|row| (row.target_unit_id, row.amount))
+            .collect();
+        unit_infos.insert(
+            unit.unit_id,
+            BattleUnitInfo {
+                unit_id: unit.unit_id,
+                amount: unit.amount,
+                attack_power: Some(unit.attack_power),
+                shield_points: Some(unit.shield_points),
+                hull_plating: Some(unit.hull_plating),
+                base_stats: None,
+                rapidfire: unit_rapidfire,
+                cost: None,
+                unit_type: None,
+            },
+        );
+    }
+
+    unit_infos
+}
+
+/// Get the caller's array as a slice. A null pointer is only allowed for an empty array.
+///
+/// # Safety
+///
+/// `data` must be null or valid for `len` elements.
+unsafe fn slice<'a, T>(data: *const T, len: usize, field: &str) -> Result<&'a [T], BattleError> {
+    if len == 0 {
+        return Ok(&[]);
+    }
+    if data.is_null() {
+        return Err(BattleError::new(BattleErrorCode::NullPointer, format!("{} is a null pointer", field)).with_field(field));
+    }
+
+    Ok(std::slice::from_raw_parts(data, len))
+}
+
+/// Hand an array over to the caller. Empty arrays are returned as null.
+fn into_array<T>(values: Vec<T>) -> *mut T {
+    if values.is_empty() {
+        return ptr::null_mut();
+    }
+
+    Box::into_raw(values.into_boxed_slice()) as *mut T
+}
+
+/// Free an array that was handed over to the caller with `into_array`.
+///
+/// # Safety
+///
+/// `data` must be null or returned by `into_array` for a vector with `len` elements.
+unsafe fn free_array<T>(data: *mut T, len: usize) {
+    if !data.is_null() {
+        drop(Box::from_raw(ptr::slice_from_raw_parts_mut(data, len)));
+    }
+}
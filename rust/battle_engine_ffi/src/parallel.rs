@@ -0,0 +1,282 @@
+use crate::arithmetic::Points;
+use crate::units::UnitStore;
+use crate::{apply_shot, record_hits, BattleRng, BattleRound, CombatParams};
+use rand::{Rng, SeedableRng};
+use std::thread;
+
+/// Environment variable which sets the amount of threads when the battle input does not.
+pub const THREADS_ENV_VAR: &str = "BATTLE_ENGINE_THREADS";
+
+/// Maximum amount of threads a single battle can use.
+pub const MAX_THREADS: usize = 64;
+
+/// A shot which hit a unit and still has to be applied to it.
+#[derive(Clone, Copy)]
+struct Shot<P> {
+    /// Index of the target unit.
+    target_idx: u32,
+    damage: P,
+}
+
+/// Shots generated by a single thread, with the statistics of the hits.
+struct GeneratedShots<P> {
+    shots: Vec<Shot<P>>,
+    /// Amount of fired shots, including bounced shots.
+    fired: u64,
+    hits: u32,
+    full_strength: f64,
+}
+
+/// Result of applying the shots on a single range of targets.
+#[derive(Default)]
+struct AppliedShots {
+    absorbed_damage: f64,
+    /// Amount of shots that bounced off the current shields of their target.
+    bounced: u32,
+    /// Total damage of the bounced shots.
+    bounced_strength: f64,
+}
+
+/// Get the amount of threads for the battle: the amount from the battle input, or else from the
+/// `BATTLE_ENGINE_THREADS` environment variable, or else 1.
+///
+/// Invalid values in the environment variable are ignored, as the PHP client can't be told about them.
+pub fn resolve_threads(input_threads: Option<usize>) -> usize {
+    input_threads
+        .or_else(|| std::env::var(THREADS_ENV_VAR).ok()?.trim().parse().ok())
+        .filter(|threads| (1..=MAX_THREADS).contains(threads))
+        .unwrap_or(1)
+}
+
+/// Estimated memory in bytes which is used to buffer the given amount of shots in a combat phase.
+///
+/// Shots are copied from the buffers of the generating threads into a single list and from there into
+/// the bucket of their target range, so up to three copies exist at the same time.
+pub fn shot_memory(shots: f64) -> f64 {
+    // Float and integer points have the same size.
+    shots * (3 * std::mem::size_of::<Shot<f32>>()) as f64
+}
+
+/// Multi-threaded version of `process_combat`, which processes a combat phase in two steps.
+///
+/// 1. The shooting units are split into equal chunks. Every thread selects the targets of its chunk
+///    and rolls for rapidfire with its own RNG stream. Whether rapidfire applies only depends on the
+///    type of the target, so this doesn't need the state of the targets. With the legacy rule set
+///    bounced shots are dropped here, as they only depend on the original shield points of the target.
+/// 2. The targets are split into equal ranges. Every thread applies the shots on the targets in its
+///    range and rolls for explosions with its own RNG stream. Shots are applied in the order they were
+///    generated, so the damage is merged in a fixed order. With the official rule set shots bounce
+///    off based on the current shield points here.
+///
+/// The seeds of all RNG streams are drawn from the battle RNG, so the result is reproducible for a
+/// given seed and amount of threads. It differs from the result of the single-threaded engine.
+///
+/// Returns the amount of fired shots, including bounced shots.
+pub fn process_combat_parallel<P: Points>(
+    attackers: &UnitStore<P>,
+    defenders: &mut UnitStore<P>,
+    round: &mut BattleRound,
+    combat: &CombatParams,
+    is_attacker: bool,
+    rng: &mut BattleRng,
+    threads: usize,
+) -> u64 {
+    let target_count = defenders.len();
+    if target_count == 0 {
+        return 0;
+    }
+
+    // Shots store the target index in 32 bits to save memory.
+    if target_count > u32::MAX as usize {
+        return crate::process_combat(attackers, defenders, round, combat, is_attacker, rng);
+    }
+
+    // Step 1: generate the shots of every chunk of shooters in parallel.
+    let chunks = split_shooters(attackers, threads);
+    let seeds: Vec<u64> = chunks.iter().map(|_| rng.gen()).collect();
+    let targets: &UnitStore<P> = defenders;
+    let generated: Vec<GeneratedShots<P>> = thread::scope(|scope| {
+        let handles: Vec<_> = chunks
+            .iter()
+            .zip(seeds)
+            .map(|(chunk, seed)| {
+                scope.spawn(move || {
+                    let mut rng = BattleRng::seed_from_u64(seed);
+                    let mut generated = GeneratedShots {
+                        shots: Vec::new(),
+                        fired: 0,
+                        hits: 0,
+                        full_strength: 0.0,
+                    };
+
+                    for &(attacker_group_idx, amount) in chunk {
+                        let damage = attackers.group(attacker_group_idx).attack_power;
+                        for _ in 0..amount {
+                            loop {
+                                let target_idx = rng.gen_range(0..target_count);
+                                let (_, target_group) = targets.group_at(target_idx);
+                                generated.fired += 1;
+
+                                // With the legacy rule set shots below the bounce threshold don't hit and end
+                                // the rapidfire chain.
+                                if combat.rule_set.bounce_ends_rapidfire()
+                                    && damage.is_below_percentage(target_group.shield_points, combat.shield_bounce_percentage)
+                                {
+                                    break;
+                                }
+
+                                generated.shots.push(Shot {
+                                    target_idx: target_idx as u32,
+                                    damage,
+                                });
+                                generated.hits += 1;
+                                generated.full_strength += damage.to_f64();
+
+                                let rapidfire = attackers.rapidfire_chance(attacker_group_idx, target_group.type_idx);
+                                match rapidfire {
+                                    Some(rapidfire_chance) if rng.gen_range(0.0..100.0) <= rapidfire_chance => {}
+                                    _ => break,
+                                }
+                            }
+                        }
+                    }
+
+                    generated
+                })
+            })
+            .collect();
+
+        handles.into_iter().map(|handle| handle.join().expect("shot generation thread panicked")).collect()
+    });
+
+    // Undamaged units of a group are split out before the shots are applied, so every shot refers to
+    // an individually stored unit.
+    let mut shots: Vec<Shot<P>> = Vec::with_capacity(generated.iter().map(|generated| generated.shots.len()).sum());
+    let mut fired = 0;
+    let mut hits = 0;
+    let mut full_strength = 0.0;
+    for generated in generated {
+        shots.extend(generated.shots);
+        fired += generated.fired;
+        hits += generated.hits;
+        full_strength += generated.full_strength;
+    }
+    split_hit_pristine_units(defenders, &mut shots);
+
+    // Step 2: apply the shots to every range of targets in parallel.
+    let unit_count = defenders.individual_len();
+    let range_size = unit_count.div_ceil(threads).max(1);
+    let mut buckets: Vec<Vec<Shot<P>>> = vec![Vec::new(); unit_count.div_ceil(range_size)];
+    for shot in shots {
+        buckets[shot.target_idx as usize / range_size].push(shot);
+    }
+
+    let seeds: Vec<u64> = buckets.iter().map(|_| rng.gen()).collect();
+    let (groups, unit_groups, current_shield_points, current_hull_plating) = defenders.individual_units_mut();
+    let applied: Vec<AppliedShots> = thread::scope(|scope| {
+        let handles: Vec<_> = buckets
+            .iter()
+            .zip(seeds)
+            .zip(unit_groups.chunks(range_size))
+            .zip(current_shield_points.chunks_mut(range_size).zip(current_hull_plating.chunks_mut(range_size)))
+            .enumerate()
+            .map(|(range_idx, (((bucket, seed), unit_groups), (current_shield_points, current_hull_plating)))| {
+                scope.spawn(move || {
+                    let mut rng = BattleRng::seed_from_u64(seed);
+                    let mut applied = AppliedShots::default();
+                    let offset = range_idx * range_size;
+
+                    for shot in bucket {
+                        let index = shot.target_idx as usize - offset;
+                        let shield_absorption = apply_shot(
+                            shot.damage,
+                            &mut current_shield_points[index],
+                            &mut current_hull_plating[index],
+                            &groups[unit_groups[index] as usize],
+                            combat,
+                            &mut rng,
+                        );
+                        match shield_absorption {
+                            Some(shield_absorption) => applied.absorbed_damage += shield_absorption.to_f64(),
+                            None => {
+                                applied.bounced += 1;
+                                applied.bounced_strength += shot.damage.to_f64();
+                            }
+                        }
+                    }
+
+                    applied
+                })
+            })
+            .collect();
+
+        handles.into_iter().map(|handle| handle.join().expect("shot application thread panicked")).collect()
+    });
+
+    // Shots which bounced off while they were applied are not counted as hits. This only happens with
+    // the official rule set, so the statistics of legacy battles are not changed by the subtraction.
+    let mut absorbed_damage = 0.0;
+    for applied in applied {
+        hits -= applied.bounced;
+        full_strength -= applied.bounced_strength;
+        absorbed_damage += applied.absorbed_damage;
+    }
+    record_hits(round, is_attacker, hits, full_strength, absorbed_damage);
+
+    fired
+}
+
+/// Split the shooting units into chunks with about the same amount of shots, as lists of
+/// `(group_idx, amount)` pairs.
+fn split_shooters<P: Points>(attackers: &UnitStore<P>, threads: usize) -> Vec<Vec<(usize, u32)>> {
+    let chunk_size = attackers.len().div_ceil(threads).max(1);
+    let mut chunks = vec![Vec::new()];
+    let mut current_size = 0;
+    for (group_idx, _, mut amount) in attackers.shooters() {
+        while amount > 0 {
+            if current_size == chunk_size {
+                chunks.push(Vec::new());
+                current_size = 0;
+            }
+
+            let taken = amount.min((chunk_size - current_size) as u32);
+            let chunk = chunks.last_mut().expect("chunks is never empty");
+            // Merge consecutive shooters of the same group, individual units of a group are mostly
+            // stored next to each other.
+            match chunk.last_mut() {
+                Some((last_group_idx, last_amount)) if *last_group_idx == group_idx => *last_amount += taken,
+                _ => chunk.push((group_idx, taken)),
+            }
+
+            amount -= taken;
+            current_size += taken as usize;
+        }
+    }
+
+    chunks
+}
+
+/// Split all undamaged units that are hit by a shot out of their groups and update the target index
+/// of the shots to the individually stored unit.
+fn split_hit_pristine_units<P: Points>(defenders: &mut UnitStore<P>, shots: &mut [Shot<P>]) {
+    let individual_len = defenders.individual_len();
+    let mut pristine_indices: Vec<usize> = shots
+        .iter()
+        .map(|shot| shot.target_idx as usize)
+        .filter(|target_idx| *target_idx >= individual_len)
+        .collect();
+    if pristine_indices.is_empty() {
+        return;
+    }
+
+    pristine_indices.sort_unstable();
+    pristine_indices.dedup();
+    let new_indices = defenders.split_pristine(&pristine_indices);
+
+    for shot in shots.iter_mut().filter(|shot| shot.target_idx as usize >= individual_len) {
+        let position = pristine_indices
+            .binary_search(&(shot.target_idx as usize))
+            .expect("all pristine targets are split out");
+        shot.target_idx = new_indices[position] as u32;
+    }
+}
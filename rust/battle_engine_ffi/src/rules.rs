@@ -0,0 +1,141 @@
+use serde::{Deserialize, Serialize};
+
+/// Combat rule parameters which can be configured per universe by the PHP client.
+///
+/// All parameters are optional in the battle input. Missing parameters fall back to the
+/// default OGame values.
+///
+/// The layout is C compatible, so the rules can be passed to `fight_battle_rounds_native` as well.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(default)]
+#[repr(C)]
+pub struct BattleRules {
+    /// Maximum amount of rounds that are fought, at most `MAX_ROUNDS`. If both sides still have units
+    /// left after the last round the battle ends in a draw.
+    pub max_rounds: u32,
+    /// Shots with damage below this percentage of the target's shield points bounce off without
+    /// doing any damage.
+    pub shield_bounce_percentage: f32,
+    /// Units with hull plating below this percentage of their original hull plating can explode
+    /// when they are hit.
+    pub explosion_hull_percentage: f32,
+    /// Percentage of the original shield points that is regenerated at the end of every round.
+    pub shield_regeneration_percentage: f32,
+    /// Chance in percent for every destroyed defense unit to be repaired after the battle.
+    pub defense_repair_percentage: f32,
+    /// Lowest unit id that counts as defense for the defense repair.
+    pub defense_unit_id_min: i16,
+    /// Highest unit id that counts as defense for the defense repair.
+    pub defense_unit_id_max: i16,
+}
+
+/// Highest amount of rounds a battle can have. Every round is kept in memory and returned in the
+/// battle output, so the amount of rounds is limited.
+pub const MAX_ROUNDS: u32 = 100;
+
+impl BattleRules {
+    /// Whether the unit id is in the range of unit ids that counts as defense.
+    pub fn is_defense(&self, unit_id: i16) -> bool {
+        (self.defense_unit_id_min..=self.defense_unit_id_max).contains(&unit_id)
+    }
+}
+
+impl Default for BattleRules {
+    fn default() -> Self {
+        BattleRules {
+            max_rounds: 6,
+            shield_bounce_percentage: 1.0,
+            explosion_hull_percentage: 70.0,
+            shield_regeneration_percentage: 100.0,
+            defense_repair_percentage: 70.0,
+            defense_unit_id_min: 401,
+            defense_unit_id_max: 499,
+        }
+    }
+}
+
+/// Set of combat details which differ between the original OGameX engine and official OGame.
+///
+/// The details are switched together, so a universe can move to the official behaviour at once. The
+/// rule set is part of the battle input, as battle reports can only be replayed with the same rule set.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RuleSet {
+    /// Behaviour of the original OGameX engine, which all battles before the rule set was added used.
+    /// - The rapidfire chance is rounded down to two decimals.
+    /// - Shots bounce off based on the original shield points of the target.
+    /// - A bounced shot ends the rapidfire chain.
+    /// - A unit can explode from a shot which is fully absorbed by its shields.
+    #[default]
+    OgamexLegacy,
+    /// Behaviour of official OGame.
+    /// - The rapidfire chance is exactly `100 - 100 / amount`.
+    /// - Shots bounce off based on the current shield points of the target, so a unit without shields
+    ///   left is hit by any shot.
+    /// - Rapidfire is rolled after a bounced shot as well, as it only depends on the type of the target.
+    /// - Only shots which damage the hull can make a unit explode.
+    OgameOfficial,
+}
+
+/// Highest rapidfire amount a unit can have. With a higher amount the legacy rapidfire chance is
+/// rounded to 100%, so the unit would never stop firing.
+pub const MAX_RAPIDFIRE: u16 = 10_000;
+
+impl RuleSet {
+    /// Calculate the chance in percent that a unit can fire again after hitting a unit it has
+    /// rapidfire against.
+    ///
+    /// Rapidfire chance is calculated as 100 - (100 / amount). The legacy rule set rounds the second
+    /// part down to two decimals. For example:
+    /// - rapidfire amount of 4 means 100 - (100 / 4) = 75% chance.
+    /// - rapidfire amount of 10 means 100 - (100 / 10) = 90% chance.
+    /// - rapidfire amount of 33 means 100 - (100 / 33) = 96.97% (legacy) or 96.9697% (official).
+    pub fn rapidfire_chance(self, rapidfire_amount: u16) -> f64 {
+        let chance = 100.0 / rapidfire_amount as f64;
+        match self {
+            RuleSet::OgamexLegacy => 100.0 - (chance * 100.0).floor() / 100.0,
+            RuleSet::OgameOfficial => 100.0 - chance,
+        }
+    }
+
+    /// Whether shots bounce off based on the current shield points of the target instead of its
+    /// original shield points.
+    pub fn bounces_off_current_shields(self) -> bool {
+        self == RuleSet::OgameOfficial
+    }
+
+    /// Whether a bounced shot ends the rapidfire chain of the shooting unit.
+    pub fn bounce_ends_rapidfire(self) -> bool {
+        self == RuleSet::OgamexLegacy
+    }
+
+    /// Whether a unit can explode from a shot which is fully absorbed by its shields.
+    pub fn explodes_without_hull_damage(self) -> bool {
+        self == RuleSet::OgamexLegacy
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn legacy_rapidfire_chance_is_rounded_down() {
+        assert_eq!(RuleSet::OgamexLegacy.rapidfire_chance(4), 75.0);
+        assert_eq!(RuleSet::OgamexLegacy.rapidfire_chance(33), 96.97);
+        assert_eq!(RuleSet::OgamexLegacy.rapidfire_chance(3), 66.67);
+    }
+
+    #[test]
+    fn official_rapidfire_chance_is_exact() {
+        assert_eq!(RuleSet::OgameOfficial.rapidfire_chance(4), 75.0);
+        assert!((RuleSet::OgameOfficial.rapidfire_chance(33) - (100.0 - 100.0 / 33.0)).abs() < 1e-12);
+        assert!(RuleSet::OgameOfficial.rapidfire_chance(3) < RuleSet::OgamexLegacy.rapidfire_chance(3));
+    }
+
+    #[test]
+    fn rule_set_defaults_to_legacy() {
+        assert_eq!(RuleSet::default(), RuleSet::OgamexLegacy);
+        assert_eq!(serde_json::from_str::<RuleSet>("\"ogame_official\"").unwrap(), RuleSet::OgameOfficial);
+    }
+}
@@ -0,0 +1,155 @@
+use crate::bonuses::{Officer, PlayerClass};
+use serde::{Deserialize, Serialize};
+
+/// Base stats of a unit as defined by the game object, before any research bonus is applied.
+///
+/// Units can provide these instead of their effective `attack_power`, `shield_points` and
+/// `hull_plating`, so the engine derives the effective stats from the technologies of the participant.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct BaseStats {
+    pub attack: f32,
+    pub shield: f32,
+    /// Structural integrity of the unit, which is 10 times its hull plating.
+    pub structural_integrity: f32,
+}
+
+/// Combat technologies and bonuses of a participant, used to derive the effective stats of units with
+/// base stats.
+///
+/// Every research level gives a bonus of 10% of the base stat, the bonus percentages are added on top
+/// of that, e.g. for bonuses of items. The class and officers add the bonuses of the bonus table of the
+/// battle input, see `BonusTable`. All fields default to 0 or none.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct Technologies {
+    pub weapon_technology: u32,
+    pub shielding_technology: u32,
+    pub armor_technology: u32,
+    pub attack_bonus_percentage: f32,
+    pub shield_bonus_percentage: f32,
+    pub structural_integrity_bonus_percentage: f32,
+    pub player_class: Option<PlayerClass>,
+    pub officers: Vec<Officer>,
+}
+
+/// Effective stats of a unit which are used during the battle.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct UnitStats {
+    pub attack_power: f32,
+    pub shield_points: f32,
+    pub hull_plating: f32,
+}
+
+impl Technologies {
+    /// Derive the effective stats from the base stats the same way as the PHP property services do.
+    ///
+    /// Every stat is the base stat plus the bonus percentage of the base stat, truncated to a whole
+    /// number. The hull plating is a tenth of the structural integrity, rounded down. The bonuses are
+    /// calculated with 64-bit floating point numbers like PHP does.
+    pub fn derive_stats(&self, base_stats: &BaseStats) -> UnitStats {
+        let attack = with_bonus(base_stats.attack, level_percentage(self.weapon_technology) + self.attack_bonus_percentage as f64);
+        let shield = with_bonus(base_stats.shield, level_percentage(self.shielding_technology) + self.shield_bonus_percentage as f64);
+        let structural_integrity = with_bonus(
+            base_stats.structural_integrity,
+            level_percentage(self.armor_technology) + self.structural_integrity_bonus_percentage as f64,
+        );
+
+        UnitStats {
+            attack_power: attack as f32,
+            shield_points: shield as f32,
+            hull_plating: (structural_integrity / 10.0).floor() as f32,
+        }
+    }
+
+    /// Reverse of `derive_stats`: get the base stats from effective stats which were derived with these
+    /// technologies. The base stats of game objects are whole numbers, so they are rounded to undo the
+    /// truncation of the effective stats.
+    pub fn base_stats(&self, stats: &UnitStats) -> BaseStats {
+        let without_bonus = |value: f32, percentage: f64| (value as f64 / (1.0 + percentage / 100.0)).round() as f32;
+
+        BaseStats {
+            attack: without_bonus(stats.attack_power, level_percentage(self.weapon_technology) + self.attack_bonus_percentage as f64),
+            shield: without_bonus(stats.shield_points, level_percentage(self.shielding_technology) + self.shield_bonus_percentage as f64),
+            structural_integrity: without_bonus(
+                stats.hull_plating * 10.0,
+                level_percentage(self.armor_technology) + self.structural_integrity_bonus_percentage as f64,
+            ),
+        }
+    }
+}
+
+/// Bonus percentage of a research level.
+fn level_percentage(level: u32) -> f64 {
+    level as f64 * 10.0
+}
+
+/// Add the bonus percentage of the base value to the base value, truncated to a whole number.
+fn with_bonus(base_value: f32, percentage: f64) -> f64 {
+    let base_value = base_value as f64;
+    (base_value + base_value / 100.0 * percentage).trunc()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stats_are_derived_like_the_php_property_services() {
+        let technologies = Technologies {
+            weapon_technology: 14,
+            shielding_technology: 13,
+            armor_technology: 15,
+            ..Technologies::default()
+        };
+        // Battlecruiser: 700 attack, 400 shield and 70,000 structural integrity.
+        let base_stats = BaseStats {
+            attack: 700.0,
+            shield: 400.0,
+            structural_integrity: 70_000.0,
+        };
+
+        let stats = technologies.derive_stats(&base_stats);
+        assert_eq!(stats.attack_power, 1680.0);
+        assert_eq!(stats.shield_points, 920.0);
+        assert_eq!(stats.hull_plating, 17_500.0);
+    }
+
+    #[test]
+    fn derived_stats_are_truncated() {
+        let technologies = Technologies {
+            attack_bonus_percentage: 2.5,
+            structural_integrity_bonus_percentage: 2.5,
+            ..Technologies::default()
+        };
+        // Light fighter: 50 attack, 10 shield and 4,000 structural integrity.
+        let base_stats = BaseStats {
+            attack: 50.0,
+            shield: 10.0,
+            structural_integrity: 4_000.0,
+        };
+
+        let stats = technologies.derive_stats(&base_stats);
+        assert_eq!(stats.attack_power, 51.0);
+        assert_eq!(stats.shield_points, 10.0);
+        assert_eq!(stats.hull_plating, 410.0);
+    }
+
+    #[test]
+    fn base_stats_are_recovered_from_effective_stats() {
+        let technologies = Technologies {
+            weapon_technology: 14,
+            shielding_technology: 13,
+            armor_technology: 15,
+            attack_bonus_percentage: 2.5,
+            ..Technologies::default()
+        };
+        let base_stats = BaseStats {
+            attack: 50.0,
+            shield: 10.0,
+            structural_integrity: 4_000.0,
+        };
+
+        let stats = technologies.derive_stats(&base_stats);
+        assert_eq!(technologies.base_stats(&stats), base_stats);
+    }
+}
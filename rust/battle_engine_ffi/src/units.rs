@@ -0,0 +1,391 @@
+use crate::arithmetic::Points;
+use crate::rules::RuleSet;
+use crate::{increment_battle_unit_count_amount, BattleParticipant, BattleUnitCount, BattleUnitInfo, ParticipantRound};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// How the units of a side are stored during the battle.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum UnitStrategy {
+    /// Every unit is stored individually from the start of the battle. Memory usage scales with the
+    /// fleet size.
+    #[default]
+    Expanded,
+    /// Undamaged units of the same type are stored as a single counted group and a unit is only stored
+    /// individually once it takes damage. Memory usage scales with the amount of damaged units, which
+    /// makes battles with millions of units feasible. The results are statistically the same as with
+    /// expanded units, but a seed gives a different battle than with expanded units.
+    Aggregated,
+}
+
+/// Dense index for every unit id that takes part in the battle, shared by both sides.
+///
+/// This is used to look up rapidfire chances in a flat table instead of a HashMap per shot.
+pub struct UnitTypeIndex {
+    indices: HashMap<i16, u16>,
+}
+
+impl UnitTypeIndex {
+    /// Create the index for the unit ids of all participants, in order of unit id.
+    pub fn new(attackers: &[BattleParticipant], defenders: &[BattleParticipant]) -> Self {
+        let mut unit_ids: Vec<i16> = attackers
+            .iter()
+            .chain(defenders)
+            .flat_map(|participant| participant.units.keys().copied())
+            .collect();
+        unit_ids.sort();
+        unit_ids.dedup();
+
+        let indices = unit_ids.into_iter().enumerate().map(|(index, unit_id)| (unit_id, index as u16)).collect();
+        UnitTypeIndex { indices }
+    }
+
+    /// Amount of distinct unit types.
+    pub fn len(&self) -> usize {
+        self.indices.len()
+    }
+
+    /// Dense index of a unit id. The unit id must be part of the battle.
+    pub fn index(&self, unit_id: i16) -> u16 {
+        self.indices[&unit_id]
+    }
+}
+
+/// Units of the same type of a single participant.
+///
+/// The stats are copied from the unit metadata so the combat loop does not have to look them up.
+pub struct UnitGroup<P> {
+    /// Index of the participant this group belongs to in the participant list of its side.
+    pub participant_idx: u16,
+    pub unit_id: i16,
+    /// Dense index of the unit id, see `UnitTypeIndex`.
+    pub type_idx: u16,
+    pub attack_power: P,
+    pub shield_points: P,
+    pub hull_plating: P,
+    /// Amount of undamaged units which are not stored individually.
+    pub pristine: u32,
+}
+
+/// Mutable state of a single unit which is hit by a shot.
+pub struct Target<'a, P> {
+    pub current_shield_points: &'a mut P,
+    pub current_hull_plating: &'a mut P,
+    pub group: &'a UnitGroup<P>,
+}
+
+/// Groups of a unit store together with the group index, current shield points and current hull
+/// plating of its individual units, see `UnitStore::individual_units_mut`.
+pub type IndividualUnitsMut<'a, P> = (&'a [UnitGroup<P>], &'a [u32], &'a mut [P], &'a mut [P]);
+
+/// Units of one side of the battle.
+///
+/// Units are addressed by an index in the range `0..len()`. Individually stored units come first,
+/// followed by the undamaged units of every group. Individual units are never reordered, so with the
+/// expanded strategy a unit keeps the same index until destroyed units are removed at the end of
+/// the round.
+///
+/// Individual units are stored as struct of arrays: the combat loop only touches the shield and hull
+/// of the target, so keeping those in separate arrays keeps more units in the CPU cache.
+///
+/// Shield points, hull plating and damage are stored as `P`, see `Arithmetic`.
+pub struct UnitStore<P> {
+    strategy: UnitStrategy,
+    groups: Vec<UnitGroup<P>>,
+    /// Rapidfire chance in percent per group and target unit type, indexed by
+    /// `group_idx * type_count + type_idx`. `None` when the group has no rapidfire against the type.
+    rapidfire_chances: Vec<Option<f64>>,
+    type_count: usize,
+    /// Group index of every individual unit.
+    unit_groups: Vec<u32>,
+    /// Current shield points of every individual unit.
+    current_shield_points: Vec<P>,
+    /// Current hull plating of every individual unit.
+    current_hull_plating: Vec<P>,
+}
+
+impl<P: Points> UnitStore<P> {
+    /// Create the units of one side of the battle, with the rapidfire chances of the rule set.
+    ///
+    /// Groups are created in order of participant and unit id, as the iteration order of the HashMap
+    /// is random and would otherwise make seeded battles non-reproducible.
+    pub fn new(
+        participants: &[BattleParticipant],
+        unit_types: &UnitTypeIndex,
+        strategy: UnitStrategy,
+        rule_set: RuleSet,
+    ) -> Self {
+        let type_count = unit_types.len();
+        let mut groups = Vec::new();
+        let mut rapidfire_chances = Vec::new();
+        for (participant_idx, participant) in participants.iter().enumerate() {
+            let mut sorted_units: Vec<&BattleUnitInfo> = participant.units.values().collect();
+            sorted_units.sort_by_key(|unit| unit.unit_id);
+
+            for info in sorted_units {
+                groups.push(UnitGroup {
+                    participant_idx: participant_idx as u16,
+                    unit_id: info.unit_id,
+                    type_idx: unit_types.index(info.unit_id),
+                    attack_power: P::from_stat(info.attack_power()),
+                    shield_points: P::from_stat(info.shield_points()),
+                    hull_plating: P::from_stat(info.hull_plating()),
+                    pristine: info.amount,
+                });
+
+                let mut row = vec![None; type_count];
+                for (target_id, rapidfire_amount) in &info.rapidfire {
+                    // Rapidfire against units that are not part of the battle is never used.
+                    if let Some(&type_idx) = unit_types.indices.get(target_id) {
+                        row[type_idx as usize] = Some(rule_set.rapidfire_chance(*rapidfire_amount));
+                    }
+                }
+                rapidfire_chances.extend(row);
+            }
+        }
+
+        let mut store = UnitStore {
+            strategy,
+            groups,
+            rapidfire_chances,
+            type_count,
+            unit_groups: Vec::new(),
+            current_shield_points: Vec::new(),
+            current_hull_plating: Vec::new(),
+        };
+
+        // Create individual units from the provided battle unit info which contains the amount.
+        if strategy == UnitStrategy::Expanded {
+            let amount = store.groups.iter().map(|group| group.pristine as usize).sum();
+            store.unit_groups.reserve_exact(amount);
+            store.current_shield_points.reserve_exact(amount);
+            store.current_hull_plating.reserve_exact(amount);
+
+            for group_idx in 0..store.groups.len() {
+                let amount = std::mem::take(&mut store.groups[group_idx].pristine);
+                for _ in 0..amount {
+                    store.push_unit(group_idx);
+                }
+            }
+        }
+
+        store
+    }
+
+    /// Total amount of units.
+    pub fn len(&self) -> usize {
+        self.unit_groups.len() + self.groups.iter().map(|group| group.pristine as usize).sum::<usize>()
+    }
+
+    /// Whether there are no units left.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Iterate over the units which fire a shot, as `(group_idx, group, amount)` tuples.
+    ///
+    /// Every undamaged group fires once for all of its units, every individual unit fires on its own.
+    pub fn shooters(&self) -> impl Iterator<Item = (usize, &UnitGroup<P>, u32)> {
+        let individual = self
+            .unit_groups
+            .iter()
+            .map(|&group_idx| (group_idx as usize, &self.groups[group_idx as usize], 1));
+        let grouped = self
+            .groups
+            .iter()
+            .enumerate()
+            .filter(|(_, group)| group.pristine > 0)
+            .map(|(group_idx, group)| (group_idx, group, group.pristine));
+        individual.chain(grouped)
+    }
+
+    /// Get the rapidfire chance in percent of a group against a unit type, if it has rapidfire.
+    pub fn rapidfire_chance(&self, group_idx: usize, type_idx: u16) -> Option<f64> {
+        self.rapidfire_chances[group_idx * self.type_count + type_idx as usize]
+    }
+
+    /// Get the unit with the given index in the range `0..len()` so it can take damage.
+    ///
+    /// An undamaged unit of a group is split out of its group into an individual unit first.
+    pub fn target_mut(&mut self, index: usize) -> Target<'_, P> {
+        let index = if index < self.unit_groups.len() {
+            index
+        } else {
+            let group_idx = self.group_at(index).0;
+            self.groups[group_idx].pristine -= 1;
+            self.push_unit(group_idx);
+            self.unit_groups.len() - 1
+        };
+
+        Target {
+            current_shield_points: &mut self.current_shield_points[index],
+            current_hull_plating: &mut self.current_hull_plating[index],
+            group: &self.groups[self.unit_groups[index] as usize],
+        }
+    }
+
+    /// Remove the destroyed units at the end of a round. The group of every removed unit is passed
+    /// to `on_destroyed`.
+    pub fn remove_destroyed(&mut self, mut on_destroyed: impl FnMut(&UnitGroup<P>)) {
+        let groups = &self.groups;
+        retain_units(
+            &mut self.unit_groups,
+            &mut self.current_shield_points,
+            &mut self.current_hull_plating,
+            |group_idx, _, current_hull_plating| {
+                // Check if unit is fully destroyed.
+                if current_hull_plating <= P::ZERO {
+                    on_destroyed(&groups[group_idx as usize]);
+                    return false;
+                }
+
+                true
+            },
+        );
+    }
+
+    /// Regenerate the given percentage of the shields of all units at the end of a round, capped at
+    /// the original shield points. With the default regeneration of 100% shields are always fully
+    /// restored.
+    ///
+    /// With the aggregated strategy units that are fully restored are merged back into their group.
+    pub fn regenerate_shields(&mut self, regeneration_percentage: f32) {
+        let groups = &mut self.groups;
+        for (group_idx, current_shield_points) in self.unit_groups.iter().zip(self.current_shield_points.iter_mut()) {
+            let shield_points = groups[*group_idx as usize].shield_points;
+            *current_shield_points = current_shield_points.regenerate(shield_points, regeneration_percentage);
+        }
+
+        if self.strategy == UnitStrategy::Aggregated {
+            retain_units(
+                &mut self.unit_groups,
+                &mut self.current_shield_points,
+                &mut self.current_hull_plating,
+                |group_idx, current_shield_points, current_hull_plating| {
+                    let group = &mut groups[group_idx as usize];
+                    let undamaged = current_shield_points >= group.shield_points && current_hull_plating >= group.hull_plating;
+                    if undamaged {
+                        group.pristine += 1;
+                    }
+
+                    !undamaged
+                },
+            );
+        }
+    }
+
+    /// Count the units per participant and unit type into the `ships` of the participant rounds.
+    pub fn count_units(&self, participant_rounds: &mut [ParticipantRound]) {
+        for group in self.groups.iter().filter(|group| group.pristine > 0) {
+            let ships = &mut participant_rounds[group.participant_idx as usize].ships;
+            increment_battle_unit_count_amount(ships, group.unit_id, group.pristine);
+        }
+
+        for group_idx in &self.unit_groups {
+            let group = &self.groups[*group_idx as usize];
+            let ships = &mut participant_rounds[group.participant_idx as usize].ships;
+            increment_battle_unit_count_amount(ships, group.unit_id, 1);
+        }
+    }
+
+    /// Count the units per unit type over all participants.
+    pub fn count_unit_types(&self) -> HashMap<i16, BattleUnitCount> {
+        let mut counts = HashMap::new();
+        for group in self.groups.iter().filter(|group| group.pristine > 0) {
+            increment_battle_unit_count_amount(&mut counts, group.unit_id, group.pristine);
+        }
+
+        for group_idx in &self.unit_groups {
+            increment_battle_unit_count_amount(&mut counts, self.groups[*group_idx as usize].unit_id, 1);
+        }
+
+        counts
+    }
+
+    /// Get a group by its index.
+    pub fn group(&self, group_idx: usize) -> &UnitGroup<P> {
+        &self.groups[group_idx]
+    }
+
+    /// Amount of individually stored units. Their indices come before the undamaged units of the groups.
+    pub fn individual_len(&self) -> usize {
+        self.unit_groups.len()
+    }
+
+    /// Get the group of the unit with the given index in the range `0..len()` without splitting it
+    /// out of its group.
+    pub fn group_at(&self, index: usize) -> (usize, &UnitGroup<P>) {
+        let group_idx = if index < self.unit_groups.len() {
+            self.unit_groups[index] as usize
+        } else {
+            let mut remaining = index - self.unit_groups.len();
+            self.groups
+                .iter()
+                .position(|group| {
+                    if remaining < group.pristine as usize {
+                        return true;
+                    }
+                    remaining -= group.pristine as usize;
+                    false
+                })
+                .expect("unit index out of range")
+        };
+
+        (group_idx, &self.groups[group_idx])
+    }
+
+    /// Split the undamaged units with the given indices out of their groups, so every one of them is
+    /// stored individually. The indices must be sorted, unique and refer to undamaged units.
+    ///
+    /// Returns the new index of every split out unit, in the same order as the given indices.
+    pub fn split_pristine(&mut self, indices: &[usize]) -> Vec<usize> {
+        // Look up all groups first, as splitting units changes the meaning of the indices.
+        let group_indices: Vec<usize> = indices.iter().map(|index| self.group_at(*index).0).collect();
+
+        let mut new_indices = Vec::with_capacity(indices.len());
+        for group_idx in group_indices {
+            self.groups[group_idx].pristine -= 1;
+            self.push_unit(group_idx);
+            new_indices.push(self.unit_groups.len() - 1);
+        }
+
+        new_indices
+    }
+
+    /// Get the groups together with the group index, current shield points and current hull plating
+    /// of the individual units, so the units can be mutated in parallel.
+    pub fn individual_units_mut(&mut self) -> IndividualUnitsMut<'_, P> {
+        (&self.groups, &self.unit_groups, &mut self.current_shield_points, &mut self.current_hull_plating)
+    }
+
+    /// Add an undamaged individual unit of a group.
+    fn push_unit(&mut self, group_idx: usize) {
+        let group = &self.groups[group_idx];
+        self.unit_groups.push(group_idx as u32);
+        self.current_shield_points.push(group.shield_points);
+        self.current_hull_plating.push(group.hull_plating);
+    }
+}
+
+/// Keep only the individual units for which `keep` returns true, preserving their order.
+fn retain_units<P: Copy>(
+    unit_groups: &mut Vec<u32>,
+    current_shield_points: &mut Vec<P>,
+    current_hull_plating: &mut Vec<P>,
+    mut keep: impl FnMut(u32, P, P) -> bool,
+) {
+    let mut kept = 0;
+    for index in 0..unit_groups.len() {
+        if keep(unit_groups[index], current_shield_points[index], current_hull_plating[index]) {
+            unit_groups[kept] = unit_groups[index];
+            current_shield_points[kept] = current_shield_points[index];
+            current_hull_plating[kept] = current_hull_plating[index];
+            kept += 1;
+        }
+    }
+
+    unit_groups.truncate(kept);
+    current_shield_points.truncate(kept);
+    current_hull_plating.truncate(kept);
+}
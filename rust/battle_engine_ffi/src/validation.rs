@@ -0,0 +1,388 @@
+use crate::arithmetic::{Arithmetic, MAX_INTEGER_STAT};
+use crate::error::{BattleError, ValidationIssue};
+use crate::parallel;
+use crate::rules::{MAX_RAPIDFIRE, MAX_ROUNDS};
+use crate::{BaseStats, BattleInput, BonusTable, CombatBonus, BattleParticipant, BattleRules, BattleUnitInfo, DebrisSettings, FleeSettings, FleeStrength, MoonSettings, Technologies};
+use std::collections::{HashMap, HashSet};
+
+/// Validate the battle input before any battle rounds are processed.
+///
+/// All problems are collected instead of stopping at the first one, so the PHP client gets the
+/// complete list of offending fields in a single call. `unknown_fields` contains the paths of all
+/// input fields that were ignored during deserialization. They are only reported in strict mode.
+pub fn validate_battle_input(input: &BattleInput, unknown_fields: &[String]) -> Result<(), BattleError> {
+    let mut issues = Vec::new();
+
+    // The debris field calculation and the flee check by cost need the cost of every unit.
+    let cost_required_when = if input.debris.is_some() {
+        Some("debris settings are provided")
+    } else if input.flee.as_ref().is_some_and(|flee| flee.strength == FleeStrength::Cost) {
+        Some("the flee strength is cost")
+    } else {
+        None
+    };
+
+    let arithmetic = input.arithmetic;
+    // Derived stats are checked with the bonuses of the class and officers of the participant.
+    let bonus_table = &input.bonus_table;
+    let attacker_technologies = &input.attacker_technologies;
+    let defender_technologies = &input.defender_technologies;
+    let units = [
+        (&input.attacker_units, "attacker_units", attacker_technologies),
+        (&input.defender_units, "defender_units", defender_technologies),
+    ];
+    for (units, side, technologies) in units {
+        validate_units(units, side, technologies, bonus_table, cost_required_when, arithmetic, &mut issues);
+    }
+    validate_technologies(attacker_technologies, "attacker_technologies", &mut issues);
+    validate_technologies(defender_technologies, "defender_technologies", &mut issues);
+    validate_participants(&input.attackers, "attackers", bonus_table, cost_required_when, arithmetic, &mut issues);
+    validate_participants(&input.defenders, "defenders", bonus_table, cost_required_when, arithmetic, &mut issues);
+    validate_bonus_table(bonus_table, &mut issues);
+    if let Some(debris) = &input.debris {
+        validate_debris_settings(debris, &mut issues);
+    }
+    if let Some(moon) = &input.moon {
+        // The moon chance is based on the debris field.
+        if input.debris.is_none() {
+            issues.push(ValidationIssue::new("moon", "requires debris settings to be provided"));
+        }
+        validate_moon_settings(moon, &mut issues);
+    }
+    if let Some(flee) = &input.flee {
+        validate_flee_settings(flee, &mut issues);
+    }
+
+    // A side is either a single fleet or a list of participants, combining both is ambiguous.
+    if !input.attacker_units.is_empty() && !input.attackers.is_empty() {
+        issues.push(ValidationIssue::new("attackers", "can't be combined with attacker_units"));
+    }
+    if !input.defender_units.is_empty() && !input.defenders.is_empty() {
+        issues.push(ValidationIssue::new("defenders", "can't be combined with defender_units"));
+    }
+    // Participants have their own technologies.
+    if !input.attackers.is_empty() && *attacker_technologies != Technologies::default() {
+        issues.push(ValidationIssue::new("attacker_technologies", "can't be combined with attackers"));
+    }
+    if !input.defenders.is_empty() && *defender_technologies != Technologies::default() {
+        issues.push(ValidationIssue::new("defender_technologies", "can't be combined with defenders"));
+    }
+    validate_rules(&input.rules, &mut issues);
+
+    if let Some(threads) = input.threads {
+        if !(1..=parallel::MAX_THREADS).contains(&threads) {
+            issues.push(ValidationIssue::new("threads", format!("must be between 1 and {}", parallel::MAX_THREADS)));
+        }
+    }
+
+    if input.memory_budget == Some(0) {
+        issues.push(ValidationIssue::new("memory_budget", "must be greater than 0"));
+    }
+
+    if input.strict {
+        for field in unknown_fields {
+            issues.push(ValidationIssue::new(field, "unknown field"));
+        }
+    }
+
+    if issues.is_empty() {
+        return Ok(());
+    }
+
+    Err(BattleError::from_issues(issues))
+}
+
+/// Validate the participants of one side of the battle.
+fn validate_participants(
+    participants: &[BattleParticipant],
+    side: &str,
+    bonus_table: &BonusTable,
+    cost_required_when: Option<&str>,
+    arithmetic: Arithmetic,
+    issues: &mut Vec<ValidationIssue>,
+) {
+    // Units refer to their participant by a 16-bit index.
+    if participants.len() > u16::MAX as usize {
+        issues.push(ValidationIssue::new(side, format!("can't have more than {} participants", u16::MAX)));
+    }
+
+    let mut participant_ids = HashSet::new();
+    for (index, participant) in participants.iter().enumerate() {
+        if !participant_ids.insert(participant.participant_id) {
+            issues.push(ValidationIssue::new(
+                format!("{}.{}.participant_id", side, index),
+                format!("duplicate participant_id {}", participant.participant_id),
+            ));
+        }
+
+        let path = format!("{}.{}", side, index);
+        validate_units(
+            &participant.units,
+            &format!("{}.units", path),
+            &participant.technologies,
+            bonus_table,
+            cost_required_when,
+            arithmetic,
+            issues,
+        );
+        validate_technologies(&participant.technologies, &format!("{}.technologies", path), issues);
+    }
+}
+
+/// Validate the units of a single fleet.
+fn validate_units(
+    units: &HashMap<i16, BattleUnitInfo>,
+    side: &str,
+    technologies: &Technologies,
+    bonus_table: &BonusTable,
+    cost_required_when: Option<&str>,
+    arithmetic: Arithmetic,
+    issues: &mut Vec<ValidationIssue>,
+) {
+    let technologies_with_bonuses = bonus_table.apply(technologies);
+
+    // Sort by unit id so the issues are always reported in the same order.
+    let mut unit_ids: Vec<&i16> = units.keys().collect();
+    unit_ids.sort();
+
+    for unit_id in unit_ids {
+        let unit = &units[unit_id];
+        let path = format!("{}.{}", side, unit_id);
+
+        // The map key is used for metadata lookups, the unit_id for the unit instances. If they
+        // differ the engine can't find the metadata of the unit.
+        if unit.unit_id != *unit_id {
+            issues.push(ValidationIssue::new(
+                format!("{}.unit_id", path),
+                format!("unit_id {} does not match map key {}", unit.unit_id, unit_id),
+            ));
+        }
+
+        if unit.amount == 0 {
+            issues.push(ValidationIssue::new(format!("{}.amount", path), "must be greater than 0"));
+        }
+
+        // The effective stats are checked as well when they are derived from the base stats, with the
+        // issues reported on the base stat they are derived from.
+        let stats = match &unit.base_stats {
+            Some(base_stats) => {
+                validate_base_stats(unit, base_stats, &path, issues);
+                let stats = technologies_with_bonuses.derive_stats(base_stats);
+                [
+                    ("base_stats.attack", stats.attack_power),
+                    ("base_stats.shield", stats.shield_points),
+                    ("base_stats.structural_integrity", stats.hull_plating),
+                ]
+            }
+            None => {
+                let stats = [
+                    ("attack_power", unit.attack_power),
+                    ("shield_points", unit.shield_points),
+                    ("hull_plating", unit.hull_plating),
+                ];
+                for (field, stat) in stats {
+                    if stat.is_none() {
+                        issues.push(ValidationIssue::new(format!("{}.{}", path, field), "is required unless base_stats is provided"));
+                    }
+                }
+
+                // The class and officer bonuses are added to effective stats as well.
+                let mut unit = unit.clone();
+                unit.derive_stats(technologies, &technologies_with_bonuses);
+                [
+                    ("attack_power", unit.attack_power()),
+                    ("shield_points", unit.shield_points()),
+                    ("hull_plating", unit.hull_plating()),
+                ]
+            }
+        };
+        let [(attack_field, attack_power), (shield_field, shield_points), (hull_field, hull_plating)] = stats;
+
+        if !attack_power.is_finite() || attack_power < 0.0 {
+            issues.push(ValidationIssue::new(format!("{}.{}", path, attack_field), "must be a finite number >= 0"));
+        }
+
+        if !shield_points.is_finite() || shield_points < 0.0 {
+            issues.push(ValidationIssue::new(format!("{}.{}", path, shield_field), "must be a finite number >= 0"));
+        }
+
+        // Hull plating is used as divisor for the hull integrity in the explosion check.
+        if unit.base_stats.is_some() {
+            if !hull_plating.is_finite() || hull_plating <= 0.0 {
+                issues.push(ValidationIssue::new(format!("{}.{}", path, hull_field), "must give a finite hull plating greater than 0"));
+            }
+        } else if unit.hull_plating.is_some() && (!hull_plating.is_finite() || hull_plating <= 0.0) {
+            issues.push(ValidationIssue::new(format!("{}.{}", path, hull_field), "must be a finite number > 0"));
+        }
+
+        // Integer arithmetic truncates the stats to whole numbers, which must be represented exactly.
+        if arithmetic == Arithmetic::Integer {
+            for (field, stat) in stats {
+                if stat.is_finite() && stat > MAX_INTEGER_STAT {
+                    issues.push(ValidationIssue::new(
+                        format!("{}.{}", path, field),
+                        format!("must be at most {} with integer arithmetic", MAX_INTEGER_STAT),
+                    ));
+                }
+            }
+
+            if hull_plating > 0.0 && hull_plating < 1.0 {
+                issues.push(ValidationIssue::new(format!("{}.{}", path, hull_field), "must be at least 1 with integer arithmetic"));
+            }
+        }
+
+        if let (Some(reason), None) = (cost_required_when, &unit.cost) {
+            issues.push(ValidationIssue::new(format!("{}.cost", path), format!("is required when {}", reason)));
+        }
+
+        let mut rapidfire_targets: Vec<(&i16, &u16)> = unit.rapidfire.iter().collect();
+        rapidfire_targets.sort();
+        for (target_id, amount) in rapidfire_targets {
+            if *amount == 0 || *amount > MAX_RAPIDFIRE {
+                issues.push(ValidationIssue::new(
+                    format!("{}.rapidfire.{}", path, target_id),
+                    format!("must be between 1 and {}", MAX_RAPIDFIRE),
+                ));
+            }
+        }
+    }
+}
+
+/// Validate the base stats of a unit, which can't be combined with its effective stats.
+fn validate_base_stats(unit: &BattleUnitInfo, base_stats: &BaseStats, path: &str, issues: &mut Vec<ValidationIssue>) {
+    let effective_stats = [
+        ("attack_power", unit.attack_power),
+        ("shield_points", unit.shield_points),
+        ("hull_plating", unit.hull_plating),
+    ];
+    for (field, stat) in effective_stats {
+        if stat.is_some() {
+            issues.push(ValidationIssue::new(format!("{}.{}", path, field), "can't be combined with base_stats"));
+        }
+    }
+
+    let base_values = [
+        ("attack", base_stats.attack),
+        ("shield", base_stats.shield),
+        ("structural_integrity", base_stats.structural_integrity),
+    ];
+    for (field, value) in base_values {
+        if !value.is_finite() || value < 0.0 {
+            issues.push(ValidationIssue::new(format!("{}.base_stats.{}", path, field), "must be a finite number >= 0"));
+        }
+    }
+}
+
+/// Validate the technologies of a participant.
+fn validate_technologies(technologies: &Technologies, path: &str, issues: &mut Vec<ValidationIssue>) {
+    let percentages = [
+        ("attack_bonus_percentage", technologies.attack_bonus_percentage),
+        ("shield_bonus_percentage", technologies.shield_bonus_percentage),
+        ("structural_integrity_bonus_percentage", technologies.structural_integrity_bonus_percentage),
+    ];
+    for (field, percentage) in percentages {
+        if !percentage.is_finite() || percentage < 0.0 {
+            issues.push(ValidationIssue::new(format!("{}.{}", path, field), "must be a finite number >= 0"));
+        }
+    }
+
+    for (index, officer) in technologies.officers.iter().enumerate() {
+        if technologies.officers[..index].contains(officer) {
+            issues.push(ValidationIssue::new(format!("{}.officers.{}", path, index), "duplicate officer"));
+        }
+    }
+}
+
+/// Validate the combat bonuses of the player classes and officers.
+fn validate_bonus_table(bonus_table: &BonusTable, issues: &mut Vec<ValidationIssue>) {
+    // Sort by name so the issues are always reported in the same order.
+    let mut bonuses: Vec<(String, &CombatBonus)> = bonus_table
+        .classes
+        .iter()
+        .map(|(player_class, bonus)| (format!("bonus_table.classes.{}", enum_name(player_class)), bonus))
+        .chain(bonus_table.officers.iter().map(|(officer, bonus)| (format!("bonus_table.officers.{}", enum_name(officer)), bonus)))
+        .collect();
+    bonuses.sort_by(|a, b| a.0.cmp(&b.0));
+
+    for (path, bonus) in bonuses {
+        let percentages = [
+            ("attack_bonus_percentage", bonus.attack_bonus_percentage),
+            ("shield_bonus_percentage", bonus.shield_bonus_percentage),
+            ("structural_integrity_bonus_percentage", bonus.structural_integrity_bonus_percentage),
+        ];
+        for (field, percentage) in percentages {
+            if !percentage.is_finite() || percentage < 0.0 {
+                issues.push(ValidationIssue::new(format!("{}.{}", path, field), "must be a finite number >= 0"));
+            }
+        }
+
+        for (index, rapidfire) in bonus.rapidfire.iter().enumerate() {
+            if rapidfire.amount == 0 || rapidfire.amount > MAX_RAPIDFIRE {
+                issues.push(ValidationIssue::new(
+                    format!("{}.rapidfire.{}.amount", path, index),
+                    format!("must be between 1 and {}", MAX_RAPIDFIRE),
+                ));
+            }
+        }
+    }
+}
+
+/// Name of an enum variant as it appears in the battle input.
+fn enum_name<T: serde::Serialize>(value: &T) -> String {
+    serde_json::to_value(value).ok().and_then(|value| value.as_str().map(str::to_string)).unwrap_or_default()
+}
+
+/// Validate the combat rule parameters.
+fn validate_rules(rules: &BattleRules, issues: &mut Vec<ValidationIssue>) {
+    if rules.max_rounds == 0 || rules.max_rounds > MAX_ROUNDS {
+        issues.push(ValidationIssue::new("rules.max_rounds", format!("must be between 1 and {}", MAX_ROUNDS)));
+    }
+
+    let percentages = [
+        ("rules.shield_bounce_percentage", rules.shield_bounce_percentage),
+        ("rules.explosion_hull_percentage", rules.explosion_hull_percentage),
+        ("rules.shield_regeneration_percentage", rules.shield_regeneration_percentage),
+        ("rules.defense_repair_percentage", rules.defense_repair_percentage),
+    ];
+    for (field, percentage) in percentages {
+        if !percentage.is_finite() || !(0.0..=100.0).contains(&percentage) {
+            issues.push(ValidationIssue::new(field, "must be a percentage between 0 and 100"));
+        }
+    }
+
+    if rules.defense_unit_id_min > rules.defense_unit_id_max {
+        issues.push(ValidationIssue::new("rules.defense_unit_id_min", "must not be greater than defense_unit_id_max"));
+    }
+}
+
+/// Validate the debris field settings.
+fn validate_debris_settings(debris: &DebrisSettings, issues: &mut Vec<ValidationIssue>) {
+    let percentages = [
+        ("debris.from_ships_percentage", debris.from_ships_percentage),
+        ("debris.from_defense_percentage", debris.from_defense_percentage),
+    ];
+    for (field, percentage) in percentages {
+        if !percentage.is_finite() || !(0.0..=100.0).contains(&percentage) {
+            issues.push(ValidationIssue::new(field, "must be a percentage between 0 and 100"));
+        }
+    }
+}
+
+/// Validate the flee settings.
+fn validate_flee_settings(flee: &FleeSettings, issues: &mut Vec<ValidationIssue>) {
+    if !flee.ratio.is_finite() || flee.ratio <= 0.0 {
+        issues.push(ValidationIssue::new("flee.ratio", "must be a finite number > 0"));
+    }
+}
+
+/// Validate the moon settings.
+fn validate_moon_settings(moon: &MoonSettings, issues: &mut Vec<ValidationIssue>) {
+    if moon.max_chance > 100 {
+        issues.push(ValidationIssue::new("moon.max_chance", "must be a percentage between 0 and 100"));
+    }
+
+    // Used as divisor for the moon chance.
+    if moon.debris_per_percent == 0 {
+        issues.push(ValidationIssue::new("moon.debris_per_percent", "must be greater than 0"));
+    }
+}
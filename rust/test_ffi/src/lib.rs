@@ -1,7 +1,22 @@
 #[no_mangle]
 pub extern "C" fn rust_hello() -> *mut u8 {
     let message = "Hello from Rust!";
-    // Convert to C string and leak memory (since we're returning to C/PHP)
+    // Convert to C string and hand ownership to the caller (C/PHP). The caller must give it back
+    // through free_rust_hello.
     let c_str = std::ffi::CString::new(message).unwrap();
     c_str.into_raw() as *mut u8
-}
\ No newline at end of file
+}
+
+/// Free a string that was returned by `rust_hello`. Passing a null pointer is a no-op.
+///
+/// # Safety
+///
+/// `ptr` must be a pointer returned by `rust_hello` that has not been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn free_rust_hello(ptr: *mut u8) {
+    if ptr.is_null() {
+        return;
+    }
+
+    drop(std::ffi::CString::from_raw(ptr as *mut std::ffi::c_char));
+}
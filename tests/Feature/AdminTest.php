@@ -56,4 +56,26 @@ class AdminTest extends AccountTestCase
             $response->assertStatus(200);
         }
     }
+
+    /**
+     * Verify that invalid battle settings are rejected instead of being saved.
+     */
+    public function testAdminServerSettingsRejectInvalidBattleSettings(): void
+    {
+        $this->artisan('ogamex:assign-admin-role', ['username' => auth()->user()->username]);
+
+        $response = $this->post('/admin/server-settings', [
+            'battle_engine' => 'rust',
+            'battle_max_rounds' => '',
+            'battle_shield_bounce_percentage' => '1.5',
+            'battle_explosion_hull_percentage' => '150',
+            'battle_shield_regeneration_percentage' => '100',
+            'battle_defense_repair_percentage' => '70',
+            'battle_rule_set' => 'unknown',
+            'battle_arithmetic' => 'float',
+        ]);
+
+        $response->assertSessionHasErrors(['battle_max_rounds', 'battle_explosion_hull_percentage', 'battle_rule_set']);
+        $response->assertSessionDoesntHaveErrors(['battle_shield_bounce_percentage', 'battle_arithmetic']);
+    }
 }
@@ -428,6 +428,8 @@ class FleetDispatchAttackTest extends FleetDispatchTestCase
         // when comparing resources before and after battle.
         $settingsService = resolve(SettingsService::class);
         $settingsService->set('economy_speed', 0);
+        // Disable defense repair so all destroyed rocket launchers stay lost.
+        $settingsService->set('battle_defense_repair_percentage', 0);
 
         // Send fleet to a nearby foreign planet.
         // Attack with 200 light fighters, defend with 100 rocket launchers.
@@ -477,6 +479,43 @@ class FleetDispatchAttackTest extends FleetDispatchTestCase
         $this->assertGreaterThan($attackerResourcesBefore->crystal, $this->planetService->getResources()->crystal, 'Attacker still has same amount of crystal after battle while it was expected they gained some.');
     }
 
+    /**
+     * Assert that destroyed defenses are repaired after battle and are added back to the defender planet.
+     */
+    public function testDispatchFleetCombatDefenseRepaired(): void
+    {
+        // Repair all destroyed defenses so the outcome is deterministic.
+        $settingsService = resolve(SettingsService::class);
+        $settingsService->set('battle_defense_repair_percentage', 100);
+
+        // Send fleet to a nearby foreign planet.
+        // Attack with 200 light fighters, defend with 100 rocket launchers.
+        // We expect attacker to win, after which all rocket launchers are repaired.
+        $this->planetAddUnit('light_fighter', 200);
+        $this->planetAddResources(new Resources(5000, 5000, 1000000, 0));
+
+        $unitCollection = new UnitCollection();
+        $unitCollection->addUnit(ObjectService::getUnitObjectByMachineName('light_fighter'), 200);
+        $foreignPlanet = $this->sendMissionToOtherPlayerCleanPlanet($unitCollection, new Resources(0, 0, 0, 0));
+
+        // Give the foreign planet some units to defend itself.
+        $foreignPlanet->addUnit('rocket_launcher', 100);
+
+        // Increase time by 24 hours to ensure the mission is done and fleets have returned.
+        $this->travel(24)->hours();
+
+        // Reload application to make sure the planet is not cached.
+        $this->reloadApplication();
+
+        // Do a request to trigger the update logic.
+        $response = $this->get('/overview');
+        $response->assertStatus(200);
+
+        // Assert that the defender still has all rocket launchers as they were all repaired.
+        $foreignPlanet->reloadPlanet();
+        $this->assertEquals(100, $foreignPlanet->getObjectAmount('rocket_launcher'), 'Defender does not have all rocket launchers after battle while it was expected they were all repaired.');
+    }
+
     /**
      * Assert that if attacker loses the battle, no return trip is launched.
      */
@@ -5,6 +5,7 @@ namespace Tests\Unit\BattleEngine;
 use OGame\GameMissions\BattleEngine\BattleEngine;
 use OGame\GameMissions\BattleEngine\RustBattleEngine;
 use OGame\GameObjects\Models\Units\UnitCollection;
+use OGame\Services\ObjectService;
 
 class RustBattleEngineTest extends BattleEngineTestAbstract
 {
@@ -36,4 +37,37 @@ class RustBattleEngineTest extends BattleEngineTestAbstract
         $this->createAndSetPlanetModel([]);
         $this->createAndSetUserTechModel([]);
     }
+
+    /**
+     * Test that a seeded battle gives the same result with the JSON and MessagePack wire formats.
+     */
+    public function testWireFormatsGiveSameResult(): void
+    {
+        if (!function_exists('msgpack_pack')) {
+            $this->markTestSkipped('The msgpack PHP extension is not installed.');
+        }
+
+        $this->createAndSetPlanetModel([
+            'rocket_launcher' => 100,
+            'light_laser' => 50,
+        ]);
+
+        $attackerFleet = new UnitCollection();
+        $attackerFleet->addUnit(ObjectService::getUnitObjectByMachineName('cruiser'), 50);
+
+        $results = [];
+        foreach ([RustBattleEngine::WIRE_FORMAT_JSON, RustBattleEngine::WIRE_FORMAT_MESSAGEPACK] as $wireFormat) {
+            $battleEngine = new RustBattleEngine($attackerFleet, $this->playerService, $this->planetService, $this->settingsService);
+            $battleEngine->setSeed(42);
+            $battleEngine->setWireFormat($wireFormat);
+            $results[] = $battleEngine->simulateBattle();
+        }
+
+        [$jsonResult, $messagePackResult] = $results;
+        $this->assertSame($jsonResult->battleSeed, $messagePackResult->battleSeed);
+        $this->assertCount(count($jsonResult->rounds), $messagePackResult->rounds);
+        $this->assertEquals($jsonResult->attackerUnitsResult->getAmount(), $messagePackResult->attackerUnitsResult->getAmount());
+        $this->assertEquals($jsonResult->defenderUnitsResult->getAmount(), $messagePackResult->defenderUnitsResult->getAmount());
+        $this->assertEquals($jsonResult->debris->sum(), $messagePackResult->debris->sum());
+    }
 }
@@ -17,6 +17,7 @@ class RustFfiTest extends UnitTestCase
         // Define the function signature in C syntax
         $ffi = \FFI::cdef("
             char* rust_hello(void);
+            void free_rust_hello(char* ptr);
         ", $libPath);
 
         // Call the Rust function and get the returned string
@@ -26,6 +27,10 @@ class RustFfiTest extends UnitTestCase
         // Convert the C string to a PHP string
         $output = \FFI::string($result);
 
+        // Hand the string back to Rust so it is freed by the allocator that created it.
+        /** @phpstan-ignore-next-line */
+        $ffi->free_rust_hello($result);
+
         // Assert the expected output
         $this->assertEquals("Hello from Rust!", $output);
     }